@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId, Throughput};
-use kvstore::KvStore;
+use kvstore::{KvStore, KvStoreOptions};
 use rand::{Rng, distributions::Alphanumeric};
 use tempfile::TempDir;
 
@@ -20,8 +20,7 @@ fn bench_sequential_writes(c: &mut Criterion) {
             b.iter_batched(
                 || {
                     let temp_dir = TempDir::new().unwrap();
-                    let mut store = KvStore::open(temp_dir.path()).unwrap();
-                    store.set_compaction_threshold(100 * 1024 * 1024); // 100MB to avoid compaction
+                    let store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
                     (store, temp_dir)
                 },
                 |(mut store, _temp_dir)| {
@@ -80,8 +79,7 @@ fn bench_overwrites(c: &mut Criterion) {
             b.iter_batched(
                 || {
                     let temp_dir = TempDir::new().unwrap();
-                    let mut store = KvStore::open(temp_dir.path()).unwrap();
-                    store.set_compaction_threshold(100 * 1024 * 1024);
+                    let store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
                     (store, temp_dir)
                 },
                 |(mut store, _temp_dir)| {
@@ -97,6 +95,145 @@ fn bench_overwrites(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_unchanged_overwrites(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unchanged_overwrites");
+    let iterations = 1000;
+    group.throughput(Throughput::Elements(iterations));
+
+    let setup = || {
+        let temp_dir = TempDir::new().unwrap();
+        let options = KvStoreOptions::new().skip_unchanged(true);
+        let mut store = KvStore::open_with_options(temp_dir.path(), options).unwrap();
+        store.set("same_key".to_string(), "same_value".to_string()).unwrap();
+        (store, temp_dir)
+    };
+
+    // Same skipped writes; `set` allocates owned arguments every time
+    group.bench_function("set", |b| {
+        b.iter_batched(
+            setup,
+            |(mut store, _temp_dir)| {
+                for _ in 0..iterations {
+                    store.set("same_key".to_string(), "same_value".to_string()).unwrap();
+                }
+            },
+            criterion::BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("set_ref", |b| {
+        b.iter_batched(
+            setup,
+            |(mut store, _temp_dir)| {
+                for _ in 0..iterations {
+                    store.set_ref("same_key", "same_value").unwrap();
+                }
+            },
+            criterion::BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+fn bench_open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("open");
+    group.sample_size(10); // Each open replays the whole log
+    let keys = 1_000_000;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
+    store.set_many((0..keys).map(|i| (format!("key_{}", i), random_string(10)))).unwrap();
+    drop(store);
+
+    // Index grown while replaying, or allocated once up front from `expected_keys`
+    for expected_keys in [0, keys] {
+        let label = if expected_keys == 0 { "default_capacity" } else { "presized_capacity" };
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                let options = KvStoreOptions::new().expected_keys(expected_keys);
+                black_box(KvStore::open_with_options(temp_dir.path(), options).unwrap())
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_deletes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deletes");
+    let keys = 1000;
+    group.throughput(Throughput::Elements(keys as u64));
+
+    let setup = || {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
+        store.set_many((0..keys).map(|i| (format!("key_{}", i), random_string(100)))).unwrap();
+        (store, temp_dir)
+    };
+
+    // One tombstone and flush per key, as in cache eviction
+    group.bench_function("remove", |b| {
+        b.iter_batched(
+            setup,
+            |(mut store, _temp_dir)| {
+                for i in 0..keys {
+                    store.remove(format!("key_{}", i)).unwrap();
+                }
+            },
+            criterion::BatchSize::SmallInput,
+        );
+    });
+    // All tombstones in one flush
+    group.bench_function("remove_all", |b| {
+        b.iter_batched(
+            setup,
+            |(mut store, _temp_dir)| {
+                let names: Vec<String> = (0..keys).map(|i| format!("key_{}", i)).collect();
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                store.remove_all(&names).unwrap();
+            },
+            criterion::BatchSize::SmallInput,
+        );
+    });
+    // Rejected by the index without writing
+    group.bench_function("remove_missing", |b| {
+        b.iter_batched(
+            setup,
+            |(mut store, _temp_dir)| {
+                for i in 0..keys {
+                    black_box(store.remove(format!("missing_{}", i)).is_err());
+                }
+            },
+            criterion::BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+fn bench_prefix_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prefix_reads");
+    let matching = 5000;
+    group.throughput(Throughput::Elements(matching));
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
+    // Interleave other users' keys so matching records are spread over the log
+    for i in 0..matching {
+        for user in 0..4 {
+            store.set(format!("user:{}:{}", user, i), random_string(100)).unwrap();
+        }
+    }
+
+    // One seek per key, in key order
+    let snapshot = store.snapshot().unwrap();
+    group.bench_function("snapshot_scan", |b| {
+        b.iter(|| black_box(snapshot.scan("user:1:").unwrap()));
+    });
+    // One forward pass in log order
+    group.bench_function("get_many_prefixed", |b| {
+        b.iter(|| black_box(store.get_many_prefixed("user:1:").unwrap()));
+    });
+    group.finish();
+}
+
 fn bench_compaction(c: &mut Criterion) {
     let mut group = c.benchmark_group("compaction");
     group.sample_size(10); // Compaction is slow, fewer samples
@@ -107,8 +244,7 @@ fn bench_compaction(c: &mut Criterion) {
             b.iter_batched(
                 || {
                     let temp_dir = TempDir::new().unwrap();
-                    let mut store = KvStore::open(temp_dir.path()).unwrap();
-                    store.set_compaction_threshold(100 * 1024 * 1024); // Disable auto-compaction
+                    let mut store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
                     
                     // Create lots of stale data by overwriting
                     for _ in 0..10 {
@@ -122,9 +258,7 @@ fn bench_compaction(c: &mut Criterion) {
                     (store, temp_dir)
                 },
                 |(mut store, _temp_dir)| {
-                    // Trigger manual compaction
-                    store.set_compaction_threshold(1); // Force compaction
-                    store.set("trigger".to_string(), "compaction".to_string()).unwrap();
+                    store.compact().unwrap();
                 },
                 criterion::BatchSize::SmallInput,
             );
@@ -140,8 +274,7 @@ fn bench_mixed_workload(c: &mut Criterion) {
         b.iter_batched(
             || {
                 let temp_dir = TempDir::new().unwrap();
-                let mut store = KvStore::open(temp_dir.path()).unwrap();
-                store.set_compaction_threshold(100 * 1024 * 1024);
+                let mut store = KvStore::open_with_options(temp_dir.path(), KvStoreOptions::new().auto_compaction(false)).unwrap();
                 
                 // Pre-populate
                 for i in 0..1000 {
@@ -168,12 +301,49 @@ fn bench_mixed_workload(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_negative_lookups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("negative_lookups");
+    
+    for bloom_filter in [false, true] {
+        let label = if bloom_filter { "bloom" } else { "no_bloom" };
+        group.throughput(Throughput::Elements(10000));
+        group.bench_function(label, |b| {
+            b.iter_batched(
+                || {
+                    let temp_dir = TempDir::new().unwrap();
+                    let options = KvStoreOptions::new().bloom_filter(bloom_filter);
+                    let mut store = KvStore::open_with_options(temp_dir.path(), options).unwrap();
+                    
+                    for i in 0..10000 {
+                        store.set(format!("key_{}", i), format!("value_{}", i)).unwrap();
+                    }
+                    
+                    (store, temp_dir)
+                },
+                |(store, _temp_dir)| {
+                    // None of these keys were ever written
+                    for i in 0..10000 {
+                        black_box(store.get(&format!("missing_{}", i)).unwrap());
+                    }
+                },
+                criterion::BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_sequential_writes,
     bench_random_reads,
     bench_overwrites,
+    bench_unchanged_overwrites,
+    bench_deletes,
+    bench_open,
+    bench_prefix_reads,
     bench_compaction,
-    bench_mixed_workload
+    bench_mixed_workload,
+    bench_negative_lookups
 );
 criterion_main!(benches);
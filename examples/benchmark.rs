@@ -1,13 +1,12 @@
-use kvstore::KvStore;
+use kvstore::{KvStore, KvStoreOptions};
 use std::time::Instant;
 
 fn main() -> kvstore::Result<()> {
     println!("=== KvStore Performance Benchmark ===\n");
     
     // Clean start
-    let _ = std::fs::remove_dir_all("./bench_data");
-    let mut store = KvStore::open("./bench_data")?;
-    store.set_compaction_threshold(100 * 1024 * 1024); // 100MB
+    KvStore::destroy("./bench_data")?;
+    let mut store = KvStore::open_with_options("./bench_data", KvStoreOptions::new().auto_compaction(false))?;
     
     // Benchmark 1: Sequential Writes
     println!("1. Sequential Writes (10,000 operations)");
@@ -51,18 +50,14 @@ fn main() -> kvstore::Result<()> {
         }
     }
     
-    let log_size_before = std::fs::metadata("./bench_data/store.log")?.len();
+    let report = store.compact()?;
+    let log_size_before = report.bytes_before();
+    let log_size_after = report.bytes_after();
+    let duration = report.duration();
     println!("   Log size before: {} bytes", log_size_before);
-    
-    let start = Instant::now();
-    store.set_compaction_threshold(1); // Force compaction
-    store.set("trigger".to_string(), "compaction".to_string())?;
-    let duration = start.elapsed();
-    
-    let log_size_after = std::fs::metadata("./bench_data/store.log")?.len();
     println!("   Log size after: {} bytes", log_size_after);
     println!("   Saved: {} bytes ({:.1}%)", 
-        log_size_before - log_size_after,
+        report.bytes_freed(),
         (1.0 - log_size_after as f64 / log_size_before as f64) * 100.0
     );
     println!("   Time: {:?}", duration);
@@ -74,7 +69,6 @@ fn main() -> kvstore::Result<()> {
     
     // Benchmark 5: Mixed Workload
     println!("5. Mixed Workload - 50% Reads, 50% Writes (10,000 operations)");
-    store.set_compaction_threshold(100 * 1024 * 1024); // Disable compaction
     let start = Instant::now();
     for i in 0..10_000 {
         if i % 2 == 0 {
@@ -89,7 +83,8 @@ fn main() -> kvstore::Result<()> {
     println!("   Throughput: {:.0} ops/sec\n", ops_per_sec);
     
     // Cleanup
-    std::fs::remove_dir_all("./bench_data")?;
+    drop(store);
+    KvStore::destroy("./bench_data")?;
     
     println!("=== Benchmark Complete ===");
     
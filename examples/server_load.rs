@@ -0,0 +1,59 @@
+use kvstore::client::Client;
+use kvstore::server::Server;
+use kvstore::KvStore;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENTS: usize = 16;
+const WRITES_PER_CLIENT: usize = 500;
+
+fn main() -> kvstore::Result<()> {
+    println!("=== KvStore Server Write Coalescing ===\n");
+    
+    for window in [Duration::ZERO, Duration::from_micros(500), Duration::from_millis(2)] {
+        let data_dir = "./server_load_data";
+        KvStore::destroy(data_dir)?;
+        
+        let mut server = Server::bind("127.0.0.1:0", KvStore::open(data_dir)?)?;
+        server.set_write_window(window);
+        let addr = server.local_addr()?;
+        let server = Arc::new(server);
+        let running = Arc::clone(&server);
+        let server_thread = thread::spawn(move || running.run());
+        
+        println!("Write window {:?} ({} clients x {} writes)", window, CLIENTS, WRITES_PER_CLIENT);
+        let start = Instant::now();
+        let handles: Vec<_> = (0..CLIENTS)
+            .map(|c| {
+                thread::spawn(move || -> kvstore::Result<()> {
+                    let mut client = Client::connect(addr)?;
+                    for i in 0..WRITES_PER_CLIENT {
+                        client.set(&format!("client_{}_key_{}", c, i), "value")?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        
+        for handle in handles {
+            handle.join().expect("client thread panicked")?;
+        }
+        
+        let duration = start.elapsed();
+        let total = (CLIENTS * WRITES_PER_CLIENT) as f64;
+        println!("   Time: {:?}", duration);
+        println!("   Throughput: {:.0} ops/sec\n", total / duration.as_secs_f64());
+        
+        // The store must be closed before its directory goes, or the next
+        // round's server would share it with this one.
+        server.shutdown()?;
+        server_thread.join().expect("server thread panicked")?;
+        drop(server);
+        KvStore::destroy(data_dir)?;
+    }
+    
+    println!("=== Load Test Complete ===");
+    
+    Ok(())
+}
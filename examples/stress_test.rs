@@ -4,7 +4,7 @@ use std::time::Instant;
 fn main() -> kvstore::Result<()> {
     println!("=== KvStore Stress Test ===\n");
     
-    let _ = std::fs::remove_dir_all("./stress_data");
+    KvStore::destroy("./stress_data")?;
     let mut store = KvStore::open("./stress_data")?;
     
     // Test 1: Large number of unique keys
@@ -74,7 +74,8 @@ fn main() -> kvstore::Result<()> {
     println!("Final log size: {:.2} MB", log_size as f64 / 1_000_000.0);
     
     // Cleanup
-    std::fs::remove_dir_all("./stress_data")?;
+    drop(store);
+    KvStore::destroy("./stress_data")?;
     
     println!("\n=== All Tests Passed ===");
     
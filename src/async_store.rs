@@ -0,0 +1,65 @@
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::sync::RwLock;
+
+use crate::engine::KvStore;
+use crate::error::Result;
+use crate::options::KvStoreOptions;
+
+/// Async handle to a `KvStore` for use inside a tokio runtime.
+///
+/// Every operation runs on tokio's blocking pool, so file I/O never stalls
+/// the async workers. Reads share the store; writes take it exclusively.
+/// Clones refer to the same store.
+#[derive(Clone)]
+pub struct AsyncKvStore {
+    inner: Arc<RwLock<KvStore>>,
+}
+
+impl AsyncKvStore {
+    pub fn new(store: KvStore) -> AsyncKvStore {
+        AsyncKvStore {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    pub async fn open(path: impl Into<PathBuf>) -> Result<AsyncKvStore> {
+        Self::open_with_options(path, KvStoreOptions::default()).await
+    }
+
+    pub async fn open_with_options(
+        path: impl Into<PathBuf>,
+        options: KvStoreOptions,
+    ) -> Result<AsyncKvStore> {
+        let path = path.into();
+        let store = run_blocking(move || KvStore::open_with_options(path, options)).await?;
+        Ok(AsyncKvStore::new(store))
+    }
+
+    pub async fn get(&self, key: String) -> Result<Option<String>> {
+        let inner = Arc::clone(&self.inner);
+        run_blocking(move || inner.blocking_read().get(&key)).await
+    }
+
+    pub async fn set(&self, key: String, val: String) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        run_blocking(move || inner.blocking_write().set(key, val)).await
+    }
+
+    pub async fn remove(&self, key: String) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        run_blocking(move || inner.blocking_write().remove(key)).await
+    }
+}
+
+/// Runs `f` on the blocking pool, re-raising any panic in the caller.
+async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
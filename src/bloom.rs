@@ -0,0 +1,79 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// Bits allotted per expected key; with `NUM_HASHES` this gives roughly a
+/// 1% false-positive rate at capacity.
+const BITS_PER_KEY: usize = 10;
+const NUM_HASHES: u64 = 7;
+const MIN_CAPACITY: usize = 1024;
+
+/// Fixed-size bloom filter over string keys (Kirsch-Mitzenmacher double hashing).
+/// Keys cannot be removed, so the owner rebuilds it from the live key set
+/// whenever it fills up or the log is compacted.
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    capacity: usize,
+    items: usize,
+}
+
+impl BloomFilter {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let words = (capacity * BITS_PER_KEY).div_ceil(64);
+
+        BloomFilter {
+            bits: vec![0; words],
+            num_bits: (words * 64) as u64,
+            capacity,
+            items: 0,
+        }
+    }
+
+    /// Builds a filter sized with headroom for the given keys.
+    pub(crate) fn from_keys<'a>(keys: impl ExactSizeIterator<Item = &'a String>) -> Self {
+        let mut filter = BloomFilter::with_capacity(keys.len() * 2);
+        for key in keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    pub(crate) fn insert(&mut self, key: &str) {
+        let (h1, h2) = hash_pair(key);
+        for i in 0..NUM_HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Returns false only if `key` was definitely never inserted.
+    pub(crate) fn may_contain(&self, key: &str) -> bool {
+        let (h1, h2) = hash_pair(key);
+        (0..NUM_HASHES).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// True once more keys were inserted than the filter was sized for.
+    pub(crate) fn is_saturated(&self) -> bool {
+        self.items > self.capacity
+    }
+}
+
+fn hash_pair(key: &str) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let h1 = hasher.finish();
+
+    // Feeding the first hash back in yields an independent second hash.
+    h1.hash(&mut hasher);
+    let h2 = hasher.finish() | 1;
+
+    (h1, h2)
+}
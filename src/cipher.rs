@@ -0,0 +1,93 @@
+#[cfg(feature = "encryption")]
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+
+use crate::error::{KvError, Result};
+
+/// Encrypts values for `KvStoreOptions::encryption_key`: AES-256-GCM with a
+/// fresh random nonce per record. Ciphertext and nonce are hex-encoded so
+/// they fit the JSON log. Uninhabited without the `encryption` feature, so
+/// such builds can still tell that a record is encrypted, but never decrypt it.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub(crate) struct ValueCipher(Aes256Gcm);
+
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone)]
+pub(crate) enum ValueCipher {}
+
+#[cfg(feature = "encryption")]
+impl ValueCipher {
+    pub(crate) fn new(key: &[u8; 32]) -> ValueCipher {
+        ValueCipher(Aes256Gcm::new(key.into()))
+    }
+
+    /// Encrypts `val`, returning the ciphertext and the nonce used.
+    pub(crate) fn encrypt(&self, val: &str) -> Result<(String, String)> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, val.as_bytes())
+            .map_err(|_| KvError::Decryption("encryption failed".to_string()))?;
+        Ok((to_hex(&ciphertext), to_hex(&nonce)))
+    }
+
+    /// Decrypts a value sealed by `encrypt`. A different key fails the
+    /// authentication check, as does tampering with the record.
+    pub(crate) fn decrypt(&self, val: &str, nonce: &str) -> Result<String> {
+        let nonce = from_hex(nonce).filter(|nonce| nonce.len() == 12);
+        let (Some(ciphertext), Some(nonce)) = (from_hex(val), nonce) else {
+            return Err(KvError::Decryption("malformed ciphertext".to_string()));
+        };
+        let plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| KvError::Decryption("wrong key or damaged record".to_string()))?;
+        String::from_utf8(plaintext).map_err(|_| KvError::Decryption("value is not UTF-8".to_string()))
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+impl ValueCipher {
+    pub(crate) fn encrypt(&self, _val: &str) -> Result<(String, String)> {
+        match *self {}
+    }
+
+    pub(crate) fn decrypt(&self, _val: &str, _nonce: &str) -> Result<String> {
+        match *self {}
+    }
+}
+
+impl std::fmt::Debug for ValueCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ValueCipher")
+    }
+}
+
+/// The plaintext of a stored value: `val` itself for a record written without
+/// encryption (no `nonce`), else `val` decrypted with `cipher`.
+pub(crate) fn open_value(cipher: Option<&ValueCipher>, val: String, nonce: Option<String>) -> Result<String> {
+    match (nonce, cipher) {
+        (None, _) => Ok(val),
+        (Some(nonce), Some(cipher)) => cipher.decrypt(&val, &nonce),
+        (Some(_), None) => Err(KvError::Decryption("value is encrypted and no key was given".to_string())),
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(feature = "encryption")]
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
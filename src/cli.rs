@@ -1,13 +1,26 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(name = "kvstore")]
 #[command(version, about = "A log-structured key-value store")]
+#[command(after_help = "Exit codes: 0 success, 1 error, 2 key not found, 3 log corruption")]
 pub struct Cli {
     #[arg(short, long, default_value = "./data", global = true)]
     pub data_dir: PathBuf,
 
+    /// Don't report missing keys on stderr; rely on the exit code
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print timing and compaction details to stderr
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Fail instead of creating a new store if the data dir doesn't hold one
+    #[arg(long, global = true)]
+    pub no_auto_create: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -15,11 +28,72 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Set a key-value pair
-    Set { key: String, value: String },
+    ///
+    /// The value is given as an argument, or read verbatim (including any
+    /// trailing newline) from a file or stdin; it must be valid UTF-8.
+    #[command(group(ArgGroup::new("source").required(true).args(["value", "value_file", "value_stdin"])))]
+    Set {
+        key: String,
+        value: Option<String>,
+
+        /// Read the value from a file
+        #[arg(long, value_name = "PATH")]
+        value_file: Option<PathBuf>,
+
+        /// Read the value from stdin
+        #[arg(long)]
+        value_stdin: bool,
+    },
     
     /// Get the value of a key
-    Get { key: String },
+    ///
+    /// Prints the value followed by a newline, so an empty value prints just
+    /// the newline. A missing key prints nothing and exits with 2, unless
+    /// --default is given.
+    Get {
+        key: String,
+
+        /// Print this and exit with 0 if the key is missing
+        #[arg(long, value_name = "VALUE")]
+        default: Option<String>,
+    },
     
     /// Remove a key
     Rm { key: String },
+
+    /// Rewrite the log with only live data
+    Compact {
+        /// Only report how much space compaction would reclaim
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print the store's size and operation counts
+    ///
+    /// Operation counts start at zero when a store is opened, so here they
+    /// are always zero; a long-running process reports real ones through
+    /// KvStore::metrics_json.
+    Stats {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Serve the store over the network until killed
+    #[cfg(feature = "http")]
+    Serve {
+        /// Address for the HTTP/JSON API, e.g. 127.0.0.1:8080
+        #[arg(long, value_name = "ADDR")]
+        http: String,
+    },
+}
+
+/// How `stats` prints its figures.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One `name: value` line each
+    #[default]
+    Text,
+    /// A single JSON object
+    Json,
 }
@@ -0,0 +1,160 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+};
+
+use crate::error::{KvError, Result};
+use crate::server::{percent_encode, read_message, Message};
+
+/// Client for the HTTP API served by `server::Server`.
+///
+/// Requests reuse a single keep-alive connection, which is re-established
+/// transparently if the server closes it.
+pub struct Client {
+    addr: SocketAddr,
+    conn: Option<Connection>,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// Status and body of a server response.
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        let addr = stream.peer_addr()?;
+        Ok(Client {
+            addr,
+            conn: Some(Connection::new(stream)?),
+        })
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let reply = self.request("GET", &key_path(key), b"")?;
+        match reply.status {
+            200 => String::from_utf8(reply.body)
+                .map(Some)
+                .map_err(|_| KvError::Protocol("value is not valid UTF-8".to_string())),
+            404 => Ok(None),
+            _ => Err(reply.into_error()),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, val: &str) -> Result<()> {
+        let reply = self.request("PUT", &key_path(key), val.as_bytes())?;
+        match reply.status {
+            200 | 204 => Ok(()),
+            _ => Err(reply.into_error()),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        let reply = self.request("DELETE", &key_path(key), b"")?;
+        match reply.status {
+            200 | 204 => Ok(()),
+            _ => Err(reply.into_error()),
+        }
+    }
+
+    /// Every key starting with `prefix` and its value, sorted by key, read
+    /// from one consistent snapshot on the server.
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let path = format!("/scan?prefix={}", percent_encode(prefix));
+        let reply = self.request("GET", &path, b"")?;
+        if reply.status != 200 {
+            return Err(reply.into_error());
+        }
+
+        let entries: BTreeMap<String, String> = serde_json::from_slice(&reply.body)
+            .map_err(|e| KvError::Protocol(format!("bad scan response: {}", e)))?;
+        Ok(entries.into_iter().collect())
+    }
+
+    fn request(&mut self, method: &str, path: &str, body: &[u8]) -> Result<Reply> {
+        // Sent with a single write so the request leaves in one segment.
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            self.addr,
+            body.len(),
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+
+        // The server may have closed a kept-alive connection since the last
+        // request, e.g. on restart; that gets one retry on a new connection.
+        let reused = self.conn.is_some();
+        let message = match self.exchange(&request) {
+            Err(_) if reused => self.exchange(&request)?,
+            result => result?,
+        };
+
+        let status = message
+            .start_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| KvError::Protocol(format!("bad status line: {}", message.start_line)))?;
+
+        Ok(Reply { status, body: message.body })
+    }
+
+    /// Sends `request` and reads the response, connecting first if needed.
+    /// Any failure drops the connection, so the next request starts afresh.
+    fn exchange(&mut self, request: &[u8]) -> Result<Message> {
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => self.conn.insert(Connection::new(TcpStream::connect(self.addr)?)?),
+        };
+
+        let result = conn.writer.write_all(request).map_err(KvError::from).and_then(|()| {
+            read_message(&mut conn.reader)?
+                .ok_or_else(|| KvError::Protocol("server closed the connection".to_string()))
+        });
+        if !result.as_ref().is_ok_and(|message| message.keep_alive) {
+            self.conn = None;
+        }
+        result
+    }
+}
+
+fn key_path(key: &str) -> String {
+    format!("/kv/{}", percent_encode(key))
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Connection> {
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+}
+
+impl Reply {
+    /// Maps an error response back onto the `KvError` the server reported.
+    fn into_error(self) -> KvError {
+        let body: serde_json::Value = match serde_json::from_slice(&self.body) {
+            Ok(body) => body,
+            Err(_) => return KvError::Protocol(format!("unexpected {} response", self.status)),
+        };
+        let message = body["error"].as_str().unwrap_or_default().to_string();
+
+        match (self.status, body["offset"].as_u64()) {
+            (404, _) => KvError::KeyNotFound,
+            (400, _) => KvError::InvalidKey(message),
+            (500, Some(offset)) => KvError::LogCorruption(offset),
+            _ => KvError::Protocol(format!("server returned {}: {}", self.status, message)),
+        }
+    }
+}
@@ -4,15 +4,48 @@ use serde::{Deserialize, Serialize};
 /// Only mutating operations (Set/Remove) are logged. Get is NOT persisted.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Command {
-    Set { key: String, val: String },
+    Set {
+        key: String,
+        val: String,
+        /// Writer-assigned version used by `ConflictResolution::HighestVersionWins`.
+        /// Absent for ordinary writes, which keeps their records unchanged on disk.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u64>,
+        /// Hex nonce of a value encrypted under `KvStoreOptions::encryption_key`;
+        /// `val` is then the hex ciphertext. Absent for plaintext values.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        /// When the value was written, in milliseconds since the Unix epoch by
+        /// the writing store's clock. Absent in records from older versions.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        written_at: Option<u64>,
+        /// Application schema version from `KvStore::set_versioned`, for
+        /// migrating old values on read. Absent means version 0.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        schema: Option<u32>,
+    },
     Remove { key: String },
+    /// Opens a transaction: the records up to the next `Commit` apply together.
+    Begin,
+    /// Closes the transaction opened by the preceding `Begin`. `count` is the
+    /// number of records in the batch, so replay can tell a complete batch
+    /// from one that lost a record to corruption.
+    Commit { count: u64 },
 }
 
 impl Command {
+    /// The key a data record refers to. Transaction markers have none, so
+    /// this panics on them; check `is_marker` first when reading a whole log.
     pub fn key(&self) -> &str {
         match self {
             Command::Set { key, .. } => key,
             Command::Remove { key } => key,
+            Command::Begin | Command::Commit { .. } => panic!("{:?} is a transaction marker and has no key", self),
         }
     }
+
+    /// Whether this is a `Begin` or `Commit` marker rather than a data record.
+    pub fn is_marker(&self) -> bool {
+        matches!(self, Command::Begin | Command::Commit { .. })
+    }
 }
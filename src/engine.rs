@@ -1,44 +1,388 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
     fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
-    path::PathBuf,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::bloom::BloomFilter;
+use crate::cipher::{self, ValueCipher};
+use crate::disk;
+use crate::clock::Clock;
+use crate::entry::Entry;
+use crate::flusher::Flusher;
+use crate::hint;
+use crate::meta::{self, StoreMeta};
+use crate::lru::Recency;
+use crate::metrics::{Counters, Metrics};
+use crate::normalize::{normalize_owned, IdentityNormalizer, KeyNormalizer};
 use crate::cmd::Command;
 use crate::error::{KvError, Result};
+use crate::options::{ConflictResolution, HintFormat, KeyValidator, KvStoreOptions, SyncMethod, SyncPolicy};
+use crate::snapshot::Snapshot;
+use crate::storage::{LogFile, Storage};
+use crate::store::Store;
+use crate::txn::Txn;
+
+const LOG_FILE: &str = "store.log";
+const COMPACT_FILE: &str = "store.log.compact";
+/// Where a compaction built off the write path goes, so it can't collide
+/// with a `compact` called meanwhile.
+const BACKGROUND_COMPACT_FILE: &str = "store.log.compact.bg";
+const HINT_FILE: &str = "store.hint";
+const HINT_TMP_FILE: &str = "store.hint.tmp";
+const META_FILE: &str = "store.meta";
+const META_TMP_FILE: &str = "store.meta.tmp";
+const COMPACTED_FILE: &str = "store.compacted";
+
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+/// Compaction is deferred until at least 1/`MIN_RECLAIM_DIVISOR` of the log
+/// is stale, so each rewrite of the live data pays for itself whatever the
+/// threshold or record sizes.
+const MIN_RECLAIM_DIVISOR: u64 = 4;
 
-#[derive(Debug, Clone)]
-struct LogPointer {
+/// A page of `KvStore::scan`: the entries and the cursor for the next page.
+type ScanPage = (Vec<(String, String)>, Option<String>);
+
+/// Every file a store creates in its directory; see `KvStore::destroy`.
+const STORE_FILES: &[&str] = &[
+    LOG_FILE,
+    COMPACT_FILE,
+    BACKGROUND_COMPACT_FILE,
+    HINT_FILE,
+    HINT_TMP_FILE,
+    META_FILE,
+    META_TMP_FILE,
+    COMPACTED_FILE,
+];
+
+/// Physical location of a record in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogPointer {
     offset: u64,
     len: u64,
 }
 
+impl LogPointer {
+    /// A pointer to the `len`-byte record at `offset`, for indexes maintained
+    /// outside the store; see `KvStore::from_parts`.
+    pub fn new(offset: u64, len: u64) -> LogPointer {
+        LogPointer { offset, len }
+    }
+
+    /// Byte offset of the record from the start of the log.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Length of the record in bytes, including its trailing newline.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+/// Outcome of replaying the log when a store is opened, for systems that
+/// alert on data loss instead of parsing the warnings printed to stderr.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    skipped_offsets: Vec<u64>,
+    discarded_transactions: Vec<u64>,
+    recovered_bytes: u64,
+    truncated_at: Option<u64>,
+}
+
+impl RecoveryReport {
+    /// Offsets of records that failed to parse and were skipped.
+    pub fn skipped_offsets(&self) -> &[u64] {
+        &self.skipped_offsets
+    }
+
+    /// Offsets of the `Begin` markers of transactions that were discarded:
+    /// unterminated, damaged, or cut off at the tail.
+    pub fn discarded_transactions(&self) -> &[u64] {
+        &self.discarded_transactions
+    }
+
+    /// Bytes of `Set`/`Remove` records replayed into the index.
+    pub fn recovered_bytes(&self) -> u64 {
+        self.recovered_bytes
+    }
+
+    /// Where the log was truncated to drop a torn record or uncommitted
+    /// transaction at its tail, if it was.
+    pub fn truncated_at(&self) -> Option<u64> {
+        self.truncated_at
+    }
+
+    /// Whether anything in the log was lost.
+    pub fn is_clean(&self) -> bool {
+        self.skipped_offsets.is_empty() && self.discarded_transactions.is_empty() && self.truncated_at.is_none()
+    }
+}
+
+/// A log that `KvStore::open_or_recover` couldn't use and moved aside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetAsideLog {
+    path: PathBuf,
+    reason: String,
+}
+
+impl SetAsideLog {
+    /// Where the log is now.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Why it couldn't be used, e.g. the error opening or reading it.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Cheap overview of a store on disk, from `KvStore::quick_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuickStats {
+    log_bytes: u64,
+    estimated_keys: u64,
+    from_hint: bool,
+}
+
+impl QuickStats {
+    /// Size of the log file.
+    pub fn log_bytes(&self) -> u64 {
+        self.log_bytes
+    }
+
+    /// Number of live keys. Exact unless the log holds keys written before a
+    /// key normalizer was configured that now normalize alike.
+    pub fn estimated_keys(&self) -> u64 {
+        self.estimated_keys
+    }
+
+    /// Whether the count started from the hint file written at the last
+    /// compaction, replaying only what was appended since. Without a hint
+    /// the whole log was replayed.
+    pub fn from_hint(&self) -> bool {
+        self.from_hint
+    }
+}
+
+/// What a compaction did, from `KvStore::compact`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    bytes_before: u64,
+    bytes_after: u64,
+    entries_retained: usize,
+    duration: Duration,
+}
+
+impl CompactionReport {
+    /// Size of the log before compaction.
+    pub fn bytes_before(&self) -> u64 {
+        self.bytes_before
+    }
+
+    /// Size of the compacted log.
+    pub fn bytes_after(&self) -> u64 {
+        self.bytes_after
+    }
+
+    /// Stale bytes reclaimed: how much smaller the log got.
+    pub fn bytes_freed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+
+    /// Live keys copied into the compacted log.
+    pub fn entries_retained(&self) -> usize {
+        self.entries_retained
+    }
+
+    /// Wall-clock time from starting the copy to swapping in the new log.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// How a `set` changed its key, from `KvStore::set_report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOutcome {
+    /// The key didn't exist.
+    Created,
+    /// The key held a different value.
+    Updated,
+    /// The key already held exactly this value.
+    Unchanged,
+}
+
+/// What compacting now would achieve, from `KvStore::estimate_compaction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionEstimate {
+    log_bytes: u64,
+    projected_bytes: u64,
+    stale_bytes: u64,
+    free_bytes: Option<u64>,
+}
+
+impl CompactionEstimate {
+    /// Current size of the log.
+    pub fn log_bytes(&self) -> u64 {
+        self.log_bytes
+    }
+
+    /// Expected size of the log after compaction.
+    pub fn projected_bytes(&self) -> u64 {
+        self.projected_bytes
+    }
+
+    /// Expected bytes reclaimed.
+    pub fn bytes_saved(&self) -> u64 {
+        self.log_bytes - self.projected_bytes
+    }
+
+    /// Share of the log that is stale, from 0 (none, or an empty log) to 1.
+    /// Counts tombstones kept for `tombstone_grace`, which `bytes_saved` doesn't.
+    pub fn fragmentation_ratio(&self) -> f64 {
+        if self.log_bytes == 0 {
+            return 0.0;
+        }
+        self.stale_bytes as f64 / self.log_bytes as f64
+    }
+
+    /// Free space on the filesystem where the compacted log is built (see
+    /// `KvStoreOptions::compaction_temp_dir`), or `None` if it can't be read.
+    /// Compaction needs room for `projected_bytes` there.
+    pub fn free_bytes(&self) -> Option<u64> {
+        self.free_bytes
+    }
+}
+
+/// A retained `Remove` record and when the key was removed.
+#[derive(Debug, Clone, Copy)]
+struct Tombstone {
+    ptr: LogPointer,
+    removed_at: SystemTime,
+}
+
 /// Log-structured key-value store (Bitcask model).
 /// Provides O(1) reads/writes with automatic compaction.
 pub struct KvStore {
     index: HashMap<String, LogPointer>,
-    writer: BufWriter<File>,
+    writer: BufWriter<LogFile>,
     log_path: PathBuf,
     dir_path: PathBuf,
     uncompacted: u64,
+    /// Length of the log, kept up to date by every append so it is known without I/O.
+    log_len: u64,
     threshold: u64,
+    /// Hard limit on reclaimable stale bytes; see `uncompacted_ceiling`.
+    ceiling: Option<u64>,
+    bloom: Option<BloomFilter>,
+    conflict_resolution: ConflictResolution,
+    max_log_age: Option<Duration>,
+    /// When the active log file was started, for `max_log_age`.
+    log_created: SystemTime,
+    /// Bumped each time compaction replaces the log file, so a compaction
+    /// built off the write path can tell its log is no longer the live one.
+    generation: u64,
+    /// Set while a compaction is being built off the write path.
+    building: Arc<AtomicBool>,
+    clock: Arc<dyn Clock>,
+    storage: Arc<dyn Storage>,
+    /// How long tombstones survive compaction, if they are retained at all.
+    tombstone_grace: Option<Duration>,
+    /// Tombstones of removed keys, tracked only when `tombstone_grace` is set.
+    tombstones: HashMap<String, Tombstone>,
+    sync_policy: SyncPolicy,
+    sync_method: SyncMethod,
+    hint_format: HintFormat,
+    /// Whether `set` skips writes that wouldn't change the stored value.
+    skip_unchanged: bool,
+    /// Whether reads treat a desynced index entry as a miss.
+    tolerate_index_desync: bool,
+    /// Periodic background sync, when `flush_interval` is set.
+    flusher: Option<Flusher>,
+    /// False until the index is built for a store from `open_without_index`.
+    indexed: bool,
+    /// What replaying the log at open found, once it has been replayed.
+    last_recovery: Option<RecoveryReport>,
+    normalizer: Arc<dyn KeyNormalizer>,
+    validator: Option<KeyValidator>,
+    counters: Counters,
+    /// False when a `StoreManager` compacts this store in the background,
+    /// or with `auto_compaction` off.
+    inline_compaction: bool,
+    max_keys: Option<usize>,
+    /// Initial capacity of the index built at open.
+    expected_keys: usize,
+    /// Where compacted logs are built, if not in `dir_path`.
+    compaction_temp_dir: Option<PathBuf>,
+    /// Permission bits for created files (unix).
+    file_mode: Option<u32>,
+    /// Encrypts values on append, when `encryption_key` is set.
+    cipher: Option<ValueCipher>,
+    /// Consulted by `get` on a local miss.
+    fallback: Option<Arc<dyn Store>>,
+    /// Use order of the live keys, tracked only when `max_keys` is set.
+    recency: Option<Mutex<Recency>>,
 }
 
 impl KvStore {
     /// Opens or creates a KvStore at the given directory path.
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
-        let dir_path = path.into();
-        std::fs::create_dir_all(&dir_path)?;
+        Self::open_with_options(path, KvStoreOptions::default())
+    }
+
+    /// Opens or creates a KvStore at the given directory path with custom options.
+    pub fn open_with_options(path: impl Into<PathBuf>, options: KvStoreOptions) -> Result<KvStore> {
+        Self::open_inner(path.into(), options, true)
+    }
+
+    fn open_inner(dir_path: PathBuf, options: KvStoreOptions, build_index: bool) -> Result<KvStore> {
+        std::fs::create_dir_all(&dir_path).map_err(|e| read_only_error(e.into(), &dir_path))?;
         
-        let log_path = dir_path.join("store.log");
+        let log_path = dir_path.join(LOG_FILE);
+
+        // A compaction interrupted before its rename leaves the compacted copy
+        // behind. The log itself is untouched, since it is only ever replaced
+        // whole by that rename, so the copy is just wasted space.
+        for name in [COMPACT_FILE, BACKGROUND_COMPACT_FILE] {
+            match std::fs::remove_file(dir_path.join(name)) {
+                Ok(()) => eprintln!("Warning: removed {} left by an interrupted compaction", name),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(read_only_error(e.into(), &dir_path)),
+            }
+        }
+
+        let writer = open_writer(&log_path, options.file_mode, &options.storage).map_err(|e| read_only_error(e, &dir_path))?;
+        if options.sync_policy == SyncPolicy::Always {
+            // Make sure a newly created log's directory entry is durable.
+            options.storage.sync_dir(&dir_path)?;
+        }
+        let metadata = writer.get_ref().file.metadata()?;
+
+        let threshold = match options.compaction_threshold {
+            Some(threshold) => threshold,
+            None => persisted_threshold(&dir_path).unwrap_or(DEFAULT_COMPACTION_THRESHOLD),
+        };
 
-        let writer = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&log_path)?
-        );
+        // An existing log is aged from its file creation time where the
+        // filesystem records one; a new log starts now.
+        let log_created = match metadata.created() {
+            Ok(created) if metadata.len() > 0 => created,
+            _ => options.clock.now(),
+        };
 
         let mut store = KvStore {
             index: HashMap::new(),
@@ -46,171 +390,2194 @@ impl KvStore {
             log_path: log_path.clone(),
             dir_path,
             uncompacted: 0,
-            threshold: 1024 * 1024,
+            log_len: metadata.len(),
+            threshold,
+            ceiling: options.uncompacted_ceiling,
+            bloom: None,
+            conflict_resolution: options.conflict_resolution,
+            max_log_age: options.max_log_age,
+            log_created,
+            generation: 0,
+            building: Arc::default(),
+            clock: options.clock,
+            storage: Arc::clone(&options.storage),
+            tombstone_grace: options.tombstone_grace,
+            tombstones: HashMap::new(),
+            skip_unchanged: options.skip_unchanged,
+            tolerate_index_desync: options.tolerate_index_desync,
+            sync_policy: options.sync_policy,
+            sync_method: options.sync_method,
+            hint_format: options.hint_format,
+            flusher: None,
+            indexed: true,
+            last_recovery: None,
+            normalizer: options.key_normalizer,
+            validator: options.key_validator,
+            counters: Counters::default(),
+            inline_compaction: options.auto_compaction,
+            max_keys: options.max_keys,
+            expected_keys: options.expected_keys,
+            compaction_temp_dir: options.compaction_temp_dir,
+            file_mode: options.file_mode,
+            cipher: options.cipher,
+            fallback: options.fallback,
+            recency: options.max_keys.map(|_| Mutex::default()),
         };
 
-        store.rebuild_index()?;
+        // A zero-length log (e.g. left behind by an `open` that crashed before
+        // the first write) is treated as a fresh store: nothing to replay.
+        if metadata.len() > 0 {
+            if build_index {
+                store.rebuild_index()?;
+            } else {
+                store.indexed = false;
+            }
+        }
+        if options.bloom_filter {
+            store.bloom = Some(BloomFilter::from_keys(store.index.keys()));
+        }
+        if let Some(interval) = options.flush_interval {
+            let log = store.writer.get_ref().file.try_clone()?;
+            store.flusher = Some(Flusher::start(log, Arc::clone(&store.storage), options.sync_method, interval));
+        }
         Ok(store)
     }
 
-    pub fn set_compaction_threshold(&mut self, threshold: u64) {
-        self.threshold = threshold;
+    /// Like `open`, but only opens a store that already exists: a directory
+    /// without a log, or none at all, is `KvError::InvalidDataDir` and nothing
+    /// is created. Catches a mistyped path that `open` would make a new,
+    /// empty store of.
+    pub fn open_existing(path: impl Into<PathBuf>) -> Result<KvStore> {
+        Self::open_existing_with_options(path, KvStoreOptions::default())
     }
 
-    fn rebuild_index(&mut self) -> Result<()> {
-        let mut reader = BufReader::new(File::open(&self.log_path)?);
-        let mut pos = 0u64;
-        let mut line = String::new();
-        let mut total_bytes = 0u64;
-        let mut live_bytes = 0u64;
+    /// Like `open_existing`, with custom options.
+    pub fn open_existing_with_options(path: impl Into<PathBuf>, options: KvStoreOptions) -> Result<KvStore> {
+        let dir_path = path.into();
+        if !dir_path.join(LOG_FILE).try_exists()? {
+            return Err(KvError::InvalidDataDir(dir_path));
+        }
+        Self::open_inner(dir_path, options, true)
+    }
 
-        while reader.read_line(&mut line)? > 0 {
-            let len = line.len() as u64;
-            
-            match serde_json::from_str::<Command>(line.trim()) {
-                Ok(cmd) => {
-                    match cmd {
-                        Command::Set { key, .. } => {
-                            if let Some(old_ptr) = self.index.get(&key) {
-                                live_bytes -= old_ptr.len;
-                            }
-                            self.index.insert(key, LogPointer { offset: pos, len });
-                            live_bytes += len;
-                        }
-                        Command::Remove { key } => {
-                            if let Some(old_ptr) = self.index.remove(&key) {
-                                live_bytes -= old_ptr.len;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Warning: skipping corrupted log entry at offset {}: {}", pos, e);
+    /// Like `open`, but a log that can't be opened or read, or holds nothing
+    /// readable at all, doesn't stop the store from starting: it is renamed
+    /// to `store.log.corrupt.<milliseconds since the Unix epoch>` in the same
+    /// directory and a fresh, empty store opened in its place. For services
+    /// where losing the data beats failing to start. The returned
+    /// `SetAsideLog` says where the log went and why; it is `None` if the log
+    /// was usable, damaged records included, as with `open`.
+    ///
+    /// The set-aside log is never touched again, by this or `destroy`.
+    pub fn open_or_recover(path: impl Into<PathBuf>) -> Result<(KvStore, Option<SetAsideLog>)> {
+        Self::open_or_recover_with_options(path, KvStoreOptions::default())
+    }
+
+    /// Like `open_or_recover`, with custom options; the fresh store gets them too.
+    pub fn open_or_recover_with_options(
+        path: impl Into<PathBuf>,
+        options: KvStoreOptions,
+    ) -> Result<(KvStore, Option<SetAsideLog>)> {
+        let dir_path = path.into();
+        let log_path = dir_path.join(LOG_FILE);
+
+        // Opened without an index, so nothing is truncated before the replay
+        // has been looked at.
+        let reason = match Self::open_inner(dir_path.clone(), options.clone(), false) {
+            // An empty log: nothing to replay.
+            Ok(store) if store.indexed => return Ok((store, None)),
+            Ok(mut store) => match replay_log_from(&log_path, 0, store.expected_keys, &*store.normalizer, |_| true) {
+                Ok(replay) if replay.recovered_bytes > 0 || replay.skipped.is_empty() => {
+                    store.load_replay(replay)?;
+                    return Ok((store, None));
                 }
-            }
+                Ok(replay) => format!("none of its {} records could be read", replay.skipped.len()),
+                Err(e) => e.to_string(),
+            },
+            Err(e @ KvError::Io(_)) if log_path.try_exists().unwrap_or(false) => e.to_string(),
+            Err(e) => return Err(e),
+        };
 
-            total_bytes += len;
-            pos += len;
-            line.clear();
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let aside_path = dir_path.join(format!("{}.corrupt.{}", LOG_FILE, millis));
+        std::fs::rename(&log_path, &aside_path)?;
+        // The hint describes the old log.
+        match std::fs::remove_file(dir_path.join(HINT_FILE)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
         }
+        eprintln!("Warning: unusable log moved to {} ({}); starting empty", aside_path.display(), reason);
 
-        self.uncompacted = total_bytes.saturating_sub(live_bytes);
-        Ok(())
+        let store = Self::open_inner(dir_path, options, true)?;
+        Ok((store, Some(SetAsideLog { path: aside_path, reason })))
     }
 
-    pub fn set(&mut self, key: String, val: String) -> Result<()> {
-        Self::validate_key(&key)?;
-        
-        let cmd = Command::Set { key: key.clone(), val };
-        let offset = self.append_command(&cmd)?;
-        
-        if let Some(old_ptr) = self.index.get(&key) {
-            self.uncompacted += old_ptr.len;
+    /// Opens the store at `path` without replaying the log, so opening costs
+    /// the same whatever the log size. Each `get` then scans the whole log
+    /// for its key: cheap for one-off tools that read a few keys, slow for
+    /// anything else. The first write builds the full index, after which the
+    /// store behaves exactly as if opened with `open`. Until then,
+    /// `uncompacted_bytes` and `index_memory_bytes` report 0.
+    pub fn open_without_index(path: impl Into<PathBuf>) -> Result<KvStore> {
+        Self::open_inner(path.into(), KvStoreOptions::default(), false)
+    }
+
+    /// Opens the store at `path` with an index maintained elsewhere, e.g. by
+    /// a standby that tailed the log and is being promoted, so the log isn't
+    /// replayed. `log_len` is the length of the log the index describes; a
+    /// different actual length is `KvError::StaleIndex`, and a pointer past
+    /// it is `LogCorruption`. Keys must be in normalized form. Beyond that the
+    /// index is trusted: `debug_verify` checks it against the log in full.
+    /// Tombstones aren't part of an index, so `tombstone_grace` starts empty.
+    pub fn from_parts(
+        path: impl Into<PathBuf>,
+        options: KvStoreOptions,
+        index: HashMap<String, LogPointer>,
+        log_len: u64,
+    ) -> Result<KvStore> {
+        let mut store = Self::open_inner(path.into(), options, false)?;
+        if store.log_len != log_len {
+            return Err(KvError::StaleIndex {
+                index_len: log_len,
+                log_len: store.log_len,
+            });
         }
-        
-        self.index.insert(key, LogPointer { offset: offset.0, len: offset.1 });
-        self.maybe_compact()?;
+        if let Some(ptr) = index.values().find(|ptr| ptr.offset + ptr.len > log_len) {
+            return Err(KvError::LogCorruption(ptr.offset));
+        }
+
+        let live_bytes: u64 = index.values().map(|ptr| ptr.len).sum();
+        store.uncompacted = log_len.saturating_sub(live_bytes);
+        store.index = index;
+        store.indexed = true;
+        store.rank_keys_by_position();
+        if store.bloom.is_some() {
+            store.bloom = Some(BloomFilter::from_keys(store.index.keys()));
+        }
+        Ok(store)
+    }
+
+    /// Removes the files belonging to the store at `path`, leaving any other
+    /// files in the directory untouched. The directory itself is removed only
+    /// if nothing else remains in it.
+    pub fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        let dir_path = path.as_ref();
+
+        for name in STORE_FILES {
+            match std::fs::remove_file(dir_path.join(name)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let _ = std::fs::remove_dir(dir_path);
         Ok(())
     }
 
-    pub fn get(&self, key: &str) -> Result<Option<String>> {
-        if let Some(ptr) = self.index.get(key) {
-            let mut reader = BufReader::new(File::open(&self.log_path)?);
-            reader.seek(SeekFrom::Start(ptr.offset))?;
-            
-            let mut line = String::new();
-            reader.read_line(&mut line)?;
-            
-            match serde_json::from_str::<Command>(line.trim()) {
-                Ok(Command::Set { val, .. }) => Ok(Some(val)),
-                Ok(Command::Remove { .. }) => Err(KvError::LogCorruption(ptr.offset)),
-                Err(_) => Err(KvError::LogCorruption(ptr.offset)),
+    /// Counts the keys of the store at `path` without opening it: no index is
+    /// built and the log is not modified, so this is cheap enough to sweep many
+    /// data directories. Reads the key list of the hint file written at the
+    /// last compaction plus the records appended since.
+    pub fn quick_stats(path: impl AsRef<Path>) -> Result<QuickStats> {
+        let dir_path = path.as_ref();
+        let log_path = dir_path.join(LOG_FILE);
+        let log_bytes = std::fs::metadata(&log_path)?.len();
+
+        // Nothing appended since the last compaction: the hint's count is exact.
+        let hint_path = dir_path.join(HINT_FILE);
+        if let Some((hint_len, count, _)) = hint::read_hint(&hint_path, false)? {
+            if hint_len == log_bytes {
+                return Ok(QuickStats { log_bytes, estimated_keys: count, from_hint: true });
             }
-        } else {
-            Ok(None)
         }
+
+        // A hint is only usable if the log still extends past what it covers.
+        let hint = hint::read_hint(&hint_path, true)?.filter(|(hint_len, _, _)| *hint_len <= log_bytes);
+        let (start, mut keys, from_hint) = match hint {
+            Some((hint_len, _, keys)) => (hint_len, keys, true),
+            None => (0, Default::default(), false),
+        };
+
+        let tail = replay_log_from(&log_path, start, 0, &IdentityNormalizer, |_| true)?;
+        for key in tail.tombstones.keys() {
+            keys.remove(key);
+        }
+        keys.extend(tail.index.into_keys());
+
+        Ok(QuickStats {
+            log_bytes,
+            estimated_keys: keys.len() as u64,
+            from_hint,
+        })
     }
 
-    pub fn remove(&mut self, key: String) -> Result<()> {
-        if !self.index.contains_key(&key) {
-            return Err(KvError::KeyNotFound);
+    /// Number of live keys. On a store from `open_without_index` that hasn't
+    /// been written to yet this replays the log.
+    pub fn len(&self) -> Result<usize> {
+        if self.indexed {
+            Ok(self.index.len())
+        } else {
+            Ok(replay_log(&self.log_path, &*self.normalizer, |_| true)?.index.len())
         }
+    }
 
-        let cmd = Command::Remove { key: key.clone() };
-        let offset = self.append_command(&cmd)?;
-        
-        if let Some(old_ptr) = self.index.remove(&key) {
-            self.uncompacted += old_ptr.len + offset.1;
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Estimated memory held by the in-memory index, in bytes.
+    /// Every live key is kept in RAM, so this bounds the keyset a host can hold.
+    ///
+    /// Counts the hash table's allocated slots (entry plus one control byte
+    /// each, including unused capacity) and the heap buffers of the keys.
+    pub fn index_memory_bytes(&self) -> usize {
+        let slot_size = std::mem::size_of::<(String, LogPointer)>() + 1;
+        let table_bytes = self.index.capacity() * slot_size;
+        let key_bytes: usize = self.index.keys().map(|key| key.capacity()).sum();
+        table_bytes + key_bytes
+    }
+
+    /// What rebuilding the index from the log found: skipped and discarded
+    /// records and any truncated tail. `None` if nothing was replayed, i.e. the
+    /// store was new or opened with `open_without_index` and not written yet.
+    pub fn last_recovery(&self) -> Option<&RecoveryReport> {
+        self.last_recovery.as_ref()
+    }
+
+    /// Bytes of the log not taken up by live values: overwritten values,
+    /// tombstones, transaction markers and skipped records. Tombstones kept
+    /// for `tombstone_grace` count too, though compaction doesn't reclaim
+    /// them yet; `estimate_compaction` leaves them out.
+    pub fn uncompacted_bytes(&self) -> u64 {
+        self.uncompacted
+    }
+
+    /// Bytes of stale data that trigger automatic compaction.
+    pub fn compaction_threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Sets the stale-data threshold for automatic compaction; takes effect
+    /// from the next write. Very small values (0 included) are allowed, e.g.
+    /// to force compaction in tests. Whatever the threshold, compaction also
+    /// waits until a quarter of the log is stale, so it can't run on every write.
+    ///
+    /// The threshold is saved in `store.meta` and used by later opens that
+    /// don't set one through `KvStoreOptions`.
+    pub fn set_compaction_threshold(&mut self, threshold: u64) {
+        self.threshold = threshold;
+        let meta = StoreMeta { compaction_threshold: Some(threshold) };
+        if let Err(e) = meta::write_meta(&self.dir_path.join(META_FILE), &meta, self.file_mode) {
+            eprintln!("Warning: failed to persist compaction threshold: {}", e);
         }
+    }
 
-        self.maybe_compact()?;
+    /// Operation counts since the store was opened.
+    pub fn metrics(&self) -> Metrics {
+        self.counters.snapshot()
+    }
+
+    /// `metrics` as a JSON object, for monitoring stacks that take JSON, e.g.
+    /// `{"gets":3,"sets":2,"removes":0,"compactions":0,"last_compaction_ms":null}`.
+    pub fn metrics_json(&self) -> String {
+        // Only integers and a null: serializing can't fail.
+        serde_json::to_string(&self.metrics()).unwrap_or_default()
+    }
+
+    /// Time since the last compaction by the store's clock, or `None` if it
+    /// hasn't compacted since open.
+    pub fn time_since_last_compaction(&self) -> Option<Duration> {
+        let last = self.counters.last_compaction()?;
+        Some(self.clock.now().duration_since(last).unwrap_or_default())
+    }
+
+    /// Hands buffered writes to the OS. They survive the process crashing,
+    /// but not the machine; see `sync`.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
         Ok(())
     }
 
-    fn validate_key(key: &str) -> Result<()> {
-        if key.is_empty() {
-            return Err(KvError::InvalidKey("Key cannot be empty".to_string()));
+    /// Flushes and then syncs the log to stable storage: a durability barrier
+    /// for commit points and shutdown. Every write made before it returns
+    /// survives a power loss.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.storage.sync(&self.writer.get_ref().file, self.sync_method)?;
+        Ok(())
+    }
+
+    /// Reads the whole log once, front to back, so it is in the OS page
+    /// cache and the first `get`s after `open` don't wait on the disk. The
+    /// read is sequential, the cheapest way to pull a file in, and done by
+    /// the time this returns, unlike an advisory prefetch. Only worth it when
+    /// the log fits in memory; the OS may evict it again under pressure.
+    pub fn preload(&self) -> Result<()> {
+        let mut log = File::open(&self.log_path)?;
+        io::copy(&mut log, &mut io::sink())?;
+        Ok(())
+    }
+
+    fn rebuild_index(&mut self) -> Result<()> {
+        let replay = replay_log_from(&self.log_path, 0, self.expected_keys, &*self.normalizer, |_| true)?;
+        self.load_replay(replay)
+    }
+
+    /// Indexes a replay of the whole log, cutting off a damaged tail.
+    fn load_replay(&mut self, mut replay: Replay) -> Result<()> {
+        let mut total_bytes = replay.total_bytes;
+
+        // A crash mid-commit leaves a transaction without its marker at the
+        // tail, and a crash mid-write leaves a torn record. Cut either off so
+        // later appends can't be mistaken for part of them.
+        if let Some(offset) = replay.uncommitted {
+            eprintln!("Warning: discarding uncommitted transaction at offset {}", offset);
+            replay.discarded.push(offset);
+        }
+        if let Some(offset) = replay.torn_tail {
+            eprintln!("Warning: truncating torn record at offset {}", offset);
+        }
+        let truncated_at = replay.uncommitted.into_iter().chain(replay.torn_tail).min();
+        if let Some(offset) = truncated_at {
+            self.writer.get_ref().file.set_len(offset)?;
+            self.writer.seek(SeekFrom::End(0))?;
+            self.log_len = offset;
+            total_bytes = offset;
+        }
+
+        self.index = replay.index;
+        self.uncompacted = total_bytes.saturating_sub(replay.live_bytes);
+        self.rank_keys_by_position();
+        if self.tombstone_grace.is_some() {
+            // The log doesn't record removal times; start each grace period now.
+            let removed_at = self.clock.now();
+            self.tombstones = replay
+                .tombstones
+                .into_iter()
+                .map(|(key, ptr)| (key, Tombstone { ptr, removed_at }))
+                .collect();
+        }
+        self.indexed = true;
+        self.last_recovery = Some(RecoveryReport {
+            skipped_offsets: replay.skipped,
+            discarded_transactions: replay.discarded,
+            recovered_bytes: replay.recovered_bytes,
+            truncated_at,
+        });
+        if self.bloom.is_some() {
+            self.bloom = Some(BloomFilter::from_keys(self.index.keys()));
         }
         Ok(())
     }
 
-    fn append_command(&mut self, cmd: &Command) -> Result<(u64, u64)> {
-        let offset = self.writer.stream_position()?;
-        let mut json = serde_json::to_vec(cmd)?;
-        json.push(b'\n');
-        
-        let len = json.len() as u64;
-        self.writer.write_all(&json)?;
-        self.writer.flush()?;
-        
-        Ok((offset, len))
+    /// Resets the `max_keys` use order to the order of the keys' records in
+    /// the log, for a freshly loaded index: recency itself isn't logged.
+    fn rank_keys_by_position(&mut self) {
+        if let Some(recency) = &mut self.recency {
+            let mut keys: Vec<(&String, &LogPointer)> = self.index.iter().collect();
+            keys.sort_unstable_by_key(|(_, ptr)| ptr.offset);
+            let mut rebuilt = Recency::default();
+            for (key, _) in keys {
+                rebuilt.touch(key);
+            }
+            *recency = Mutex::new(rebuilt);
+        }
     }
 
-    fn maybe_compact(&mut self) -> Result<()> {
-        if self.uncompacted > self.threshold {
-            self.compact()?;
+    /// Builds the index of a store opened with `open_without_index`; every
+    /// write starts with this, so only reads ever run unindexed.
+    fn ensure_index(&mut self) -> Result<()> {
+        if !self.indexed {
+            self.rebuild_index()?;
         }
         Ok(())
     }
 
-    fn compact(&mut self) -> Result<()> {
-        let compact_path = self.dir_path.join("store.log.compact");
-        
-        let mut tmp_writer = BufWriter::new(
-            File::create(&compact_path)
-                .map_err(|e| KvError::CompactionFailed(e.to_string()))?
-        );
-        
-        let mut new_index = HashMap::new();
-        let mut reader = BufReader::new(File::open(&self.log_path)?);
-        let mut pos = 0u64;
+    /// Where `key` lives, from the index or, for an unindexed store, a scan of the log.
+    fn locate(&self, key: &str) -> Result<Option<LogPointer>> {
+        if self.indexed {
+            Ok(self.index.get(key).copied())
+        } else {
+            let replay = replay_log(&self.log_path, &*self.normalizer, |candidate| candidate == key)?;
+            Ok(replay.index.get(key).copied())
+        }
+    }
 
-        for (key, ptr) in &self.index {
-            reader.seek(SeekFrom::Start(ptr.offset))?;
-            let mut line = String::new();
-            reader.read_line(&mut line)?;
-            
-            let len = line.len() as u64;
-            tmp_writer.write_all(line.as_bytes())?;
-            new_index.insert(key.clone(), LogPointer { offset: pos, len });
-            pos += len;
+    pub fn set(&mut self, key: String, val: String) -> Result<()> {
+        if self.skip_unchanged {
+            return self.set_if_changed(key, val).map(|_| ());
         }
+        let key = self.prepare_key(key)?;
+        self.write_set(key, val)
+    }
 
-        tmp_writer.flush()?;
-        drop(tmp_writer);
+    /// Stores `val` JSON-encoded, for structured values without hand-written
+    /// serialization. Read it back with `get_typed`.
+    pub fn set_typed<T: Serialize + ?Sized>(&mut self, key: String, val: &T) -> Result<()> {
+        let val = serde_json::to_string(val)?;
+        self.set(key, val)
+    }
 
-        std::fs::rename(&compact_path, &self.log_path)
-            .map_err(|e| KvError::CompactionFailed(e.to_string()))?;
+    /// Decodes the JSON value stored under `key`. A value that isn't valid
+    /// JSON for `T` is `KvError::Serde`.
+    pub fn get_typed<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(val) => Ok(Some(serde_json::from_str(&val)?)),
+            None => Ok(None),
+        }
+    }
 
-        self.writer = BufWriter::new(
-            OpenOptions::new()
-                .append(true)
-                .open(&self.log_path)?
-        );
-        
-        self.index = new_index;
-        self.uncompacted = 0;
+    /// Adds `val` to the front of the list stored under `key`, creating it if
+    /// absent, and returns the new length. Lists are JSON arrays of strings
+    /// in an ordinary value, so every push reads and rewrites the whole list:
+    /// O(n) in its length. A value that isn't such a list is `KvError::Serde`.
+    pub fn lpush(&mut self, key: String, val: String) -> Result<usize> {
+        self.update_list(key, |list| list.insert(0, val))
+    }
 
-        Ok(())
+    /// Like `lpush`, but adds `val` to the back of the list.
+    pub fn rpush(&mut self, key: String, val: String) -> Result<usize> {
+        self.update_list(key, |list| list.push(val))
+    }
+
+    /// The elements of the list under `key` from `start` to `stop`, both
+    /// inclusive. Negative indices count from the end (-1 is the last), and
+    /// out-of-range indices are clamped, as in Redis `LRANGE`: `(0, -1)` is
+    /// the whole list. An absent key is an empty list.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>> {
+        let mut list: Vec<String> = self.get_typed(key)?.unwrap_or_default();
+        let len = list.len() as i64;
+        let resolve = |i: i64| if i < 0 { len + i } else { i };
+        let (start, stop) = (resolve(start).max(0), resolve(stop).min(len - 1));
+        if start > stop {
+            return Ok(Vec::new());
+        }
+        Ok(list.drain(start as usize..=stop as usize).collect())
+    }
+
+    fn update_list(&mut self, key: String, update: impl FnOnce(&mut Vec<String>)) -> Result<usize> {
+        let mut list: Vec<String> = self.get_typed(&key)?.unwrap_or_default();
+        update(&mut list);
+        self.set_typed(key, &list)?;
+        Ok(list.len())
+    }
+
+    /// Sets `field` of the hash stored under `key` to `val`, creating the hash
+    /// if absent, and returns whether the field is new. A hash is a JSON
+    /// object of strings in an ordinary value, so its fields are written
+    /// together, but each `hset` rewrites all of them. A value that isn't
+    /// such an object is `KvError::Serde`.
+    pub fn hset(&mut self, key: String, field: String, val: String) -> Result<bool> {
+        let mut hash: BTreeMap<String, String> = self.get_typed(&key)?.unwrap_or_default();
+        let added = hash.insert(field, val).is_none();
+        self.set_typed(key, &hash)?;
+        Ok(added)
+    }
+
+    /// The value of `field` in the hash under `key`; `None` if either is absent.
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        Ok(self.hgetall(key)?.remove(field))
+    }
+
+    /// Every field of the hash under `key`, sorted by field. An absent key is
+    /// an empty hash.
+    pub fn hgetall(&self, key: &str) -> Result<BTreeMap<String, String>> {
+        Ok(self.get_typed(key)?.unwrap_or_default())
+    }
+
+    /// Like `set`, but skips the write when `key` already holds exactly
+    /// `val`. Returns whether a record was written. Costs a read of the
+    /// current value.
+    pub fn set_if_changed(&mut self, key: String, val: String) -> Result<bool> {
+        let key = self.prepare_key(key)?;
+        if self.holds(&key, &val)? {
+            return Ok(false);
+        }
+        self.write_set(key, val)?;
+        Ok(true)
+    }
+
+    /// Like `set`, but reports whether the key was created, changed or left as
+    /// it was, e.g. to invalidate caches only on real changes. Costs a read
+    /// of the current value. An unchanged value is still written, as by
+    /// `set`, unless `skip_unchanged` is on.
+    pub fn set_report(&mut self, key: String, val: String) -> Result<SetOutcome> {
+        let key = self.prepare_key(key)?;
+        let outcome = match self.index.get(&key) {
+            None => SetOutcome::Created,
+            Some(ptr) if self.read_set(&key, ptr)?.val == val => SetOutcome::Unchanged,
+            Some(_) => SetOutcome::Updated,
+        };
+        if !(outcome == SetOutcome::Unchanged && self.skip_unchanged) {
+            self.write_set(key, val)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Like `set`, for callers holding borrowed strings: owned copies are
+    /// only made when a record is written. With `skip_unchanged` on, an
+    /// overwrite with the same value allocates nothing for the key or value.
+    pub fn set_ref(&mut self, key: &str, val: &str) -> Result<()> {
+        let key = self.normalizer.normalize(key);
+        self.validate_key(&key)?;
+        self.ensure_index()?;
+        if self.skip_unchanged && self.holds(&key, val)? {
+            return Ok(());
+        }
+        self.write_set(key.into_owned(), val.to_string())
+    }
+
+    /// Like `set`, but only overwrites: `KeyNotFound` if `key` isn't in this
+    /// store (a `fallback` doesn't count), and nothing is written. Guards
+    /// update-only code paths against creating keys, like Redis `SET ... XX`.
+    pub fn replace(&mut self, key: &str, val: String) -> Result<()> {
+        let key = self.prepare_key(key.to_string())?;
+        if !self.index.contains_key(&key) {
+            return Err(KvError::KeyNotFound);
+        }
+        if self.skip_unchanged && self.holds(&key, &val)? {
+            return Ok(());
+        }
+        self.write_set(key, val)
+    }
+
+    /// Whether the (normalized) `key` currently holds exactly `val`.
+    fn holds(&self, key: &str, val: &str) -> Result<bool> {
+        match self.index.get(key) {
+            Some(ptr) => Ok(self.read_set(key, ptr)?.val == val),
+            None => Ok(false),
+        }
+    }
+
+    /// Normalizes and validates a key about to be written, and makes sure
+    /// the index is built.
+    fn prepare_key(&mut self, key: String) -> Result<String> {
+        let key = normalize_owned(&*self.normalizer, key);
+        self.validate_key(&key)?;
+        self.ensure_index()?;
+        Ok(key)
+    }
+
+    /// Appends a `Set` for a key that went through `prepare_key`.
+    pub(crate) fn write_set(&mut self, key: String, val: String) -> Result<()> {
+        self.write_set_with_schema(key, val, None)
+    }
+
+    /// `write_set`, tagging the record with a schema version.
+    fn write_set_with_schema(&mut self, key: String, val: String, schema: Option<u32>) -> Result<()> {
+        let cmd = Command::Set {
+            key: key.clone(),
+            val,
+            version: None,
+            nonce: None,
+            written_at: self.write_time(),
+            schema,
+        };
+        // Everything that can refuse the write happens before an eviction,
+        // so a failed `set` never costs another key.
+        self.apply_backpressure()?;
+        let record = self.encode(&cmd)?;
+        if let Some(max_keys) = self.max_keys {
+            if !self.index.contains_key(&key) {
+                self.evict_down_to(max_keys - 1)?;
+            }
+        }
+        let ptr = self.append_encoded(&record)?;
+        self.index_set(key, ptr);
+        self.maybe_compact()?;
+        Ok(())
+    }
+
+    /// Like `set`, but returns where the record landed in the log. If the
+    /// write triggered a compaction, this is the record's post-compaction location.
+    pub fn set_tracked(&mut self, key: String, val: String) -> Result<LogPointer> {
+        let lookup = normalize_owned(&*self.normalizer, key.clone());
+        self.set(key, val)?;
+        Ok(self.index[&lookup])
+    }
+
+    /// The index entry for `key`, for tools that check the index against the log.
+    /// On a store from `open_without_index` this scans the log, so it fails
+    /// if the log can't be read.
+    pub fn pointer(&self, key: &str) -> Result<Option<LogPointer>> {
+        self.locate(&self.normalizer.normalize(key))
+    }
+
+    /// The value of `key`, from the `fallback` store if it isn't here, or
+    /// `None` if it is in neither. An empty value is a value like any other:
+    /// it comes back as `Some("")`, never `None`.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.get_with_metadata(key)? {
+            Some((val, _)) => Ok(Some(val)),
+            None => match &self.fallback {
+                Some(fallback) => fallback.get(key),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Like `get`, but a value found only in the `fallback` store is also
+    /// written here, so the next read of the key is a local hit.
+    pub fn get_and_promote(&mut self, key: &str) -> Result<Option<String>> {
+        if let Some((val, _)) = self.get_with_metadata(key)? {
+            return Ok(Some(val));
+        }
+        let val = match &self.fallback {
+            Some(fallback) => fallback.get(key)?,
+            None => None,
+        };
+        if let Some(val) = &val {
+            self.set(key.to_string(), val.clone())?;
+        }
+        Ok(val)
+    }
+
+    /// Whether `get` would find `key`, without reading its value.
+    pub fn contains_key(&self, key: &str) -> Result<bool> {
+        let normalized = self.normalizer.normalize(key);
+        let bloom_miss = self.bloom.as_ref().is_some_and(|bloom| !bloom.may_contain(&normalized));
+        if !bloom_miss && self.locate(&normalized)?.is_some() {
+            return Ok(true);
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.contains_key(key),
+            None => Ok(false),
+        }
+    }
+
+    /// Like `get`, but also returns where the value's record sits in the log,
+    /// for tools correlating values with their physical location.
+    pub fn get_with_metadata(&self, key: &str) -> Result<Option<(String, LogPointer)>> {
+        Ok(self.lookup(key)?.map(|(stored, ptr)| (stored.val, ptr)))
+    }
+
+    /// Like `get`, but also returns how long ago the value was written, by
+    /// the store's clock (zero if the clock has since gone back). The age is
+    /// `None` for values written by versions that didn't record write times.
+    /// Only this store is consulted, not the `fallback`.
+    pub fn get_with_age(&self, key: &str) -> Result<Option<(String, Option<Duration>)>> {
+        let Some((stored, _)) = self.lookup(key)? else {
+            return Ok(None);
+        };
+        let age = stored.written_at.map(|written_at| {
+            let written_at = UNIX_EPOCH + Duration::from_millis(written_at);
+            self.clock.now().duration_since(written_at).unwrap_or_default()
+        });
+        Ok(Some((stored.val, age)))
+    }
+
+    /// Like `set`, but tags the value with the application's schema version,
+    /// so readers can tell old values apart and migrate them. Always writes,
+    /// even under `skip_unchanged`, since the tag may be all that changed.
+    pub fn set_versioned(&mut self, key: String, val: String, schema_version: u32) -> Result<()> {
+        let key = self.prepare_key(key)?;
+        self.write_set_with_schema(key, val, Some(schema_version))
+    }
+
+    /// Like `get`, but also returns the value's schema version from
+    /// `set_versioned`; values written any other way report version 0.
+    /// Only this store is consulted, not the `fallback`.
+    pub fn get_versioned(&self, key: &str) -> Result<Option<(String, u32)>> {
+        Ok(self.lookup(key)?.map(|(stored, _)| (stored.val, stored.schema.unwrap_or(0))))
+    }
+
+    /// The live `Set` of `key` and where it is, counted as one get.
+    fn lookup(&self, key: &str) -> Result<Option<(StoredValue, LogPointer)>> {
+        self.counters.add_gets(1);
+        let key = &*self.normalizer.normalize(key);
+        if let Some(bloom) = &self.bloom {
+            if !bloom.may_contain(key) {
+                return Ok(None);
+            }
+        }
+
+        let Some(ptr) = self.locate(key)? else {
+            return Ok(None);
+        };
+        let mut reader = BufReader::new(File::open(&self.log_path)?);
+        let record = read_record_from(&mut reader, &ptr)?;
+        match self.expect_live(record, key, &ptr)? {
+            Some(stored) => {
+                self.touch(key);
+                Ok(Some((stored, ptr)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up several keys with one handle on the log, returning the value of
+    /// each key that exists under the key as passed in. Absent keys are left
+    /// out. On a store from `open_without_index` all keys share one log scan.
+    pub fn multi_get(&self, keys: &[&str]) -> Result<HashMap<String, String>> {
+        self.counters.add_gets(keys.len() as u64);
+        let scanned;
+        let index = if self.indexed {
+            &self.index
+        } else {
+            let wanted: HashSet<Cow<str>> = keys.iter().map(|key| self.normalizer.normalize(key)).collect();
+            scanned = replay_log(&self.log_path, &*self.normalizer, |key| wanted.contains(key))?.index;
+            &scanned
+        };
+
+        let mut reader = BufReader::new(File::open(&self.log_path)?);
+        let mut found = HashMap::new();
+        for &key in keys {
+            let normalized = self.normalizer.normalize(key);
+            if let Some(ptr) = index.get(&*normalized) {
+                let record = read_record_from(&mut reader, ptr)?;
+                if let Some(stored) = self.expect_live(record, &normalized, ptr)? {
+                    self.touch(&normalized);
+                    found.insert(key.to_string(), stored.val);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Reads the `Set` of `key` at `ptr`. Anything else there means the index
+    /// and log disagree: `LogCorruption`.
+    fn read_set(&self, key: &str, ptr: &LogPointer) -> Result<StoredValue> {
+        let mut reader = BufReader::new(File::open(&self.log_path)?);
+        self.read_set_from(&mut reader, key, ptr)
+    }
+
+    /// `read_set` through an already open reader on the log.
+    fn read_set_from(&self, reader: &mut BufReader<File>, key: &str, ptr: &LogPointer) -> Result<StoredValue> {
+        let record = read_record_from(reader, ptr)?;
+        self.expect_set(record, key, ptr)
+    }
+
+    /// The contents of `record`, which the index says is the `Set` of `key` at `ptr`.
+    fn expect_set(&self, record: Command, key: &str, ptr: &LogPointer) -> Result<StoredValue> {
+        match record {
+            Command::Set { key: stored, val, version, nonce, written_at, schema } if self.normalizer.normalize(&stored) == key => {
+                Ok(StoredValue {
+                    val: cipher::open_value(self.cipher.as_ref(), val, nonce)?,
+                    version,
+                    written_at,
+                    schema,
+                })
+            }
+            _ => Err(KvError::LogCorruption(ptr.offset)),
+        }
+    }
+
+    /// `expect_set` for reads. Under `tolerate_index_desync` a record other
+    /// than a `Set` of `key` is reported and read as a miss.
+    fn expect_live(&self, record: Command, key: &str, ptr: &LogPointer) -> Result<Option<StoredValue>> {
+        if self.tolerate_index_desync {
+            let is_set_of_key = matches!(&record, Command::Set { key: stored, .. } if self.normalizer.normalize(stored) == key);
+            if !is_set_of_key {
+                eprintln!(
+                    "Warning: index entry for {:?} points at offset {}, which holds no value for it; treating it as missing",
+                    key, ptr.offset
+                );
+                return Ok(None);
+            }
+        }
+        self.expect_set(record, key, ptr).map(Some)
+    }
+
+    /// Every value `key` has been set to that is still in the log, oldest
+    /// first, including values shadowed by later writes. For auditing and
+    /// debugging: this reads the whole log, and compaction discards the
+    /// shadowed values, so only history since the last compaction is left.
+    /// Like replay, it skips corrupt records and uncommitted transactions.
+    pub fn history(&self, key: &str) -> Result<Vec<String>> {
+        let key = self.normalizer.normalize(key);
+        let mut values = Vec::new();
+        // Values set inside the open transaction, kept once it commits.
+        let mut batch: Option<Vec<String>> = None;
+        let mut batch_len = 0;
+
+        for record in read_log(&self.log_path)? {
+            let cmd = match record {
+                Ok((_, cmd)) => cmd,
+                Err(KvError::LogCorruption(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            match cmd {
+                Command::Begin => {
+                    batch = Some(Vec::new());
+                    batch_len = 0;
+                }
+                Command::Commit { count } => {
+                    if let Some(pending) = batch.take() {
+                        if batch_len == count {
+                            values.extend(pending);
+                        }
+                    }
+                }
+                Command::Set { key: stored, val, nonce, .. } => {
+                    batch_len += 1;
+                    if self.normalizer.normalize(&stored) == key {
+                        let val = cipher::open_value(self.cipher.as_ref(), val, nonce)?;
+                        match &mut batch {
+                            Some(pending) => pending.push(val),
+                            None => values.push(val),
+                        }
+                    }
+                }
+                Command::Remove { .. } => batch_len += 1,
+            }
+        }
+        Ok(values)
+    }
+
+    /// Checks the index against the log, for catching index bugs during
+    /// development: every entry must point at a `Set` of exactly that key and
+    /// length, and replaying the log must give the same keys and pointers.
+    /// Any disagreement is `LogCorruption` at the offending offset. Reads the
+    /// whole log; a store from `open_without_index` has nothing to check yet.
+    pub fn debug_verify(&self) -> Result<()> {
+        if !self.indexed {
+            return Ok(());
+        }
+
+        let mut entries: Vec<(&String, &LogPointer)> = self.index.iter().collect();
+        entries.sort_unstable_by_key(|(_, ptr)| ptr.offset);
+        let mut reader = BufReader::new(File::open(&self.log_path)?);
+        for (key, ptr) in entries {
+            reader.seek(SeekFrom::Start(ptr.offset))?;
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if line.len() as u64 != ptr.len {
+                return Err(KvError::LogCorruption(ptr.offset));
+            }
+            self.expect_set(parse_record(&line, ptr)?, key, ptr)?;
+        }
+
+        let replayed = replay_log(&self.log_path, &*self.normalizer, |_| true)?.index;
+        for (key, ptr) in &replayed {
+            if self.index.get(key) != Some(ptr) {
+                return Err(KvError::LogCorruption(ptr.offset));
+            }
+        }
+        if let Some((_, ptr)) = self.index.iter().find(|(key, _)| !replayed.contains_key(*key)) {
+            return Err(KvError::LogCorruption(ptr.offset));
+        }
+        Ok(())
+    }
+
+    /// Every key starting with `prefix` and its value, sorted by key. The
+    /// records are read in log order in one forward pass, so large prefixes
+    /// cost a sequential read instead of a seek per key. Keys are in
+    /// normalized form; the prefix is matched as given.
+    pub fn get_many_prefixed(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let scanned;
+        let index = if self.indexed {
+            &self.index
+        } else {
+            scanned = replay_log(&self.log_path, &*self.normalizer, |key| key.starts_with(prefix))?.index;
+            &scanned
+        };
+
+        let matches: Vec<(&String, &LogPointer)> = index.iter().filter(|(key, _)| key.starts_with(prefix)).collect();
+        self.read_sorted(matches)
+    }
+
+    /// Every key matching the glob `pattern` and its value, sorted by key.
+    /// `*` matches any run of characters, including none, and `?` exactly
+    /// one; there is no escaping. Unlike a prefix this can't narrow the
+    /// search, so every key is tested: O(n) in the number of keys.
+    pub fn scan_glob(&self, pattern: &str) -> Result<Vec<(String, String)>> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let scanned;
+        let index = if self.indexed {
+            &self.index
+        } else {
+            scanned = replay_log(&self.log_path, &*self.normalizer, |key| glob_match(&pattern, key))?.index;
+            &scanned
+        };
+
+        let matches: Vec<(&String, &LogPointer)> = index.iter().filter(|(key, _)| glob_match(&pattern, key)).collect();
+        self.read_sorted(matches)
+    }
+
+    /// One page of all keys and values in key order, for listing a store in
+    /// pages: up to `limit` (at least 1) entries with keys after `cursor`,
+    /// or from the first key if `cursor` is `None`. The second element is
+    /// the cursor for the next page, `None` once this page reaches the last
+    /// key. Keys are in normalized form. Pages are cut by key, not position,
+    /// so keys written between calls appear if they sort after the cursor,
+    /// and no key is ever returned twice.
+    pub fn scan(&self, cursor: Option<&str>, limit: usize) -> Result<ScanPage> {
+        let limit = limit.max(1);
+        let after = |key: &str| cursor.is_none_or(|cursor| key > cursor);
+        let scanned;
+        let index = if self.indexed {
+            &self.index
+        } else {
+            scanned = replay_log(&self.log_path, &*self.normalizer, after)?.index;
+            &scanned
+        };
+
+        let mut keys: Vec<(&String, &LogPointer)> = index.iter().filter(|(key, _)| after(key)).collect();
+        let more = keys.len() > limit;
+        if more {
+            keys.select_nth_unstable_by(limit, |a, b| a.0.cmp(b.0));
+            keys.truncate(limit);
+        }
+        let next = if more { keys.iter().map(|(key, _)| *key).max().cloned() } else { None };
+        Ok((self.read_sorted(keys)?, next))
+    }
+
+    /// Reads the values of `entries` in log order, in one forward pass, and
+    /// returns them sorted by key.
+    fn read_sorted(&self, mut entries: Vec<(&String, &LogPointer)>) -> Result<Vec<(String, String)>> {
+        entries.sort_unstable_by_key(|(_, ptr)| ptr.offset);
+        self.counters.add_gets(entries.len() as u64);
+
+        let mut reader = BufReader::new(File::open(&self.log_path)?);
+        let mut pos = 0;
+        let mut found = Vec::with_capacity(entries.len());
+        for (key, ptr) in entries {
+            // Relative seeks keep what is already buffered, unlike seeking to an absolute offset.
+            reader.seek_relative(ptr.offset as i64 - pos as i64)?;
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            pos = ptr.offset + line.len() as u64;
+
+            if let Some(stored) = self.expect_live(parse_record(&line, ptr)?, key, ptr)? {
+                self.touch(key);
+                found.push((key.clone(), stored.val));
+            }
+        }
+
+        found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(found)
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        let key = normalize_owned(&*self.normalizer, key);
+        self.ensure_index()?;
+        if !self.index.contains_key(&key) {
+            return Err(KvError::KeyNotFound);
+        }
+
+        let cmd = Command::Remove { key: key.clone() };
+        let ptr = self.append_command(&cmd)?;
+        
+        self.index_remove(&key, ptr);
+        self.maybe_compact()?;
+        Ok(())
+    }
+
+    /// Applies a command taken from another command stream (replication, merges).
+    /// Returns whether it took effect. Under `ConflictResolution::HighestVersionWins`
+    /// a versioned `Set` older than the stored value is dropped without being
+    /// logged; removing an absent key and transaction markers are no-ops.
+    pub fn apply(&mut self, cmd: Command) -> Result<bool> {
+        self.ensure_index()?;
+        match self.normalize_command(cmd) {
+            Command::Set { key, val, version, nonce, written_at, schema } => {
+                self.validate_key(&key)?;
+                if let Some(incoming) = version {
+                    if self.conflict_resolution == ConflictResolution::HighestVersionWins {
+                        if let Some(current) = self.stored_version(&key)? {
+                            if incoming < current {
+                                return Ok(false);
+                            }
+                        }
+                    }
+                }
+
+                let cmd = Command::Set { key: key.clone(), val, version, nonce, written_at, schema };
+                let ptr = self.append_command(&cmd)?;
+                self.index_set(key, ptr);
+            }
+            Command::Remove { key } => {
+                if !self.index.contains_key(&key) {
+                    return Ok(false);
+                }
+
+                let cmd = Command::Remove { key: key.clone() };
+                let ptr = self.append_command(&cmd)?;
+                self.index_remove(&key, ptr);
+            }
+            Command::Begin | Command::Commit { .. } => return Ok(false),
+        }
+
+        self.enforce_max_keys()?;
+        self.maybe_compact()?;
+        Ok(true)
+    }
+
+    /// Version of the live value for `key`, if it has one.
+    fn stored_version(&self, key: &str) -> Result<Option<u64>> {
+        match self.index.get(key) {
+            Some(ptr) => self.read_set(key, ptr).map(|stored| stored.version),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets several keys with a single write and flush. Unlike a transaction
+    /// the batch has no commit marker, so a crash may keep only a prefix of it.
+    /// Nothing is written if any key is invalid.
+    pub fn set_many(&mut self, pairs: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        let now = self.write_time();
+        let cmds = pairs
+            .into_iter()
+            .map(|(key, val)| {
+                let key = normalize_owned(&*self.normalizer, key);
+                self.validate_key(&key)?;
+                Ok(Command::Set { key, val, version: None, nonce: None, written_at: now, schema: None })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.ensure_index()?;
+        let ptrs = self.append_batch(&cmds)?;
+        for (cmd, ptr) in cmds.into_iter().zip(ptrs) {
+            if let Command::Set { key, .. } = cmd {
+                self.index_set(key, ptr);
+            }
+        }
+
+        self.enforce_max_keys()?;
+        self.maybe_compact()?;
+        Ok(())
+    }
+
+    /// Removes every present key among `keys` with a single write and flush,
+    /// returning how many were removed. Absent keys are skipped rather than
+    /// failing the call. Like `set_many`, a crash may keep only a prefix.
+    pub fn remove_all(&mut self, keys: &[&str]) -> Result<usize> {
+        self.ensure_index()?;
+        let mut seen = HashSet::new();
+        let cmds: Vec<Command> = keys
+            .iter()
+            .map(|key| normalize_owned(&*self.normalizer, key.to_string()))
+            .filter(|key| self.index.contains_key(key) && seen.insert(key.clone()))
+            .map(|key| Command::Remove { key })
+            .collect();
+        if cmds.is_empty() {
+            return Ok(0);
+        }
+
+        let ptrs = self.append_batch(&cmds)?;
+        for (cmd, ptr) in cmds.iter().zip(ptrs) {
+            if let Command::Remove { key } = cmd {
+                self.index_remove(key, ptr);
+            }
+        }
+
+        self.maybe_compact()?;
+        Ok(cmds.len())
+    }
+
+    /// Reads `key` for an update that depends on its current value, like
+    /// `HashMap::entry`. Invalid keys are rejected here, before any write.
+    pub fn entry(&mut self, key: String) -> Result<Entry<'_>> {
+        let key = self.prepare_key(key)?;
+        self.counters.add_gets(1);
+        let value = match self.index.get(&key) {
+            Some(ptr) => Some(self.read_set(&key, ptr)?.val),
+            None => None,
+        };
+        // An absent key is only tracked once the entry inserts it.
+        if value.is_some() {
+            self.touch(&key);
+        }
+        Ok(Entry::new(self, key, value))
+    }
+
+    /// Whether writes of an unchanged value are skipped (`skip_unchanged`).
+    pub(crate) fn skips_unchanged(&self) -> bool {
+        self.skip_unchanged
+    }
+
+    /// Exchanges the values of `a` and `b` as one transaction, so a crash
+    /// can't leave them half-swapped. `KeyNotFound` if either is absent.
+    pub fn swap(&mut self, a: &str, b: &str) -> Result<()> {
+        self.ensure_index()?;
+        let a = normalize_owned(&*self.normalizer, a.to_string());
+        let b = normalize_owned(&*self.normalizer, b.to_string());
+        let (Some(a_ptr), Some(b_ptr)) = (self.index.get(&a), self.index.get(&b)) else {
+            return Err(KvError::KeyNotFound);
+        };
+        if a == b {
+            return Ok(());
+        }
+
+        self.counters.add_gets(2);
+        let a_val = self.read_set(&a, a_ptr)?;
+        let b_val = self.read_set(&b, b_ptr)?;
+        // Schema versions describe the values, so they move with them.
+        self.commit_batch(vec![
+            Command::Set { key: a, val: b_val.val, version: None, nonce: None, written_at: None, schema: b_val.schema },
+            Command::Set { key: b, val: a_val.val, version: None, nonce: None, written_at: None, schema: a_val.schema },
+        ])
+    }
+
+    /// Starts a transaction whose writes land atomically on `Txn::commit`.
+    pub fn transaction(&mut self) -> Txn<'_> {
+        Txn::new(self)
+    }
+
+    /// A copy of the index, with the log length it describes, for handing to
+    /// `from_parts` later (e.g. by a standby). Replays the log on a store
+    /// from `open_without_index`.
+    pub fn export_index(&self) -> Result<(HashMap<String, LogPointer>, u64)> {
+        let index = if self.indexed {
+            self.index.clone()
+        } else {
+            replay_log(&self.log_path, &*self.normalizer, |_| true)?.index
+        };
+        Ok((index, self.log_len))
+    }
+
+    /// Takes a consistent read-only view of the current contents, unaffected
+    /// by later writes and compactions. Costs a copy of the index.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let log = File::open(&self.log_path)?;
+        let index = if self.indexed {
+            self.index.clone()
+        } else {
+            replay_log(&self.log_path, &*self.normalizer, |_| true)?.index
+        };
+        Ok(Snapshot::new(index, log, Arc::clone(&self.normalizer), self.cipher.clone()))
+    }
+
+    /// Writes `ops` as one transaction: a `Begin` marker, the records, and a
+    /// `Commit` marker in a single flush. Replay ignores a batch whose `Commit`
+    /// never reached the disk. Nothing is written if any operation is invalid.
+    pub(crate) fn commit_batch(&mut self, ops: Vec<Command>) -> Result<()> {
+        self.ensure_index()?;
+        let now = self.write_time();
+        let ops: Vec<Command> = ops
+            .into_iter()
+            .map(|op| match self.normalize_command(op) {
+                Command::Set { key, val, version, nonce, written_at, schema } => Command::Set {
+                    key,
+                    val,
+                    version,
+                    nonce,
+                    written_at: written_at.or(now),
+                    schema,
+                },
+                op => op,
+            })
+            .collect();
+        {
+            // Track presence as it evolves through the batch, so a remove of a
+            // key set earlier in the same transaction is accepted.
+            let mut present: HashMap<&str, bool> = HashMap::new();
+            for op in &ops {
+                match op {
+                    Command::Set { key, .. } => {
+                        self.validate_key(key)?;
+                        present.insert(key, true);
+                    }
+                    Command::Remove { key } => {
+                        let exists = present
+                            .get(key.as_str())
+                            .copied()
+                            .unwrap_or_else(|| self.index.contains_key(key));
+                        if !exists {
+                            return Err(KvError::KeyNotFound);
+                        }
+                        present.insert(key, false);
+                    }
+                    Command::Begin | Command::Commit { .. } => {}
+                }
+            }
+        }
+
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let count = ops.len() as u64;
+        let mut records = Vec::with_capacity(ops.len() + 2);
+        records.push(Command::Begin);
+        records.extend(ops);
+        records.push(Command::Commit { count });
+
+        let ptrs = self.append_batch(&records)?;
+        for (cmd, ptr) in records.into_iter().zip(ptrs) {
+            match cmd {
+                Command::Set { key, .. } => self.index_set(key, ptr),
+                Command::Remove { key } => self.index_remove(&key, ptr),
+                Command::Begin | Command::Commit { .. } => self.uncompacted += ptr.len,
+            }
+        }
+
+        self.enforce_max_keys()?;
+        self.maybe_compact()?;
+        Ok(())
+    }
+
+    /// Points `key` at a freshly appended `Set` record; the record it replaces becomes stale.
+    fn index_set(&mut self, key: String, ptr: LogPointer) {
+        self.counters.add_set();
+        self.touch(&key);
+        self.bloom_insert(&key);
+        self.tombstones.remove(&key);
+        if let Some(old_ptr) = self.index.insert(key, ptr) {
+            self.uncompacted += old_ptr.len;
+        }
+    }
+
+    /// Drops `key` once its tombstone is appended; the old record and the tombstone are both stale.
+    fn index_remove(&mut self, key: &str, tombstone: LogPointer) {
+        self.counters.add_remove();
+        if let Some(recency) = &self.recency {
+            recency.lock().unwrap_or_else(PoisonError::into_inner).forget(key);
+        }
+        if let Some(old_ptr) = self.index.remove(key) {
+            self.uncompacted += old_ptr.len + tombstone.len;
+        }
+        if self.tombstone_grace.is_some() {
+            let removed_at = self.clock.now();
+            self.tombstones.insert(key.to_string(), Tombstone { ptr: tombstone, removed_at });
+        }
+    }
+
+    /// Marks `key` as just used, when `max_keys` is set.
+    fn touch(&self, key: &str) {
+        if let Some(recency) = &self.recency {
+            recency.lock().unwrap_or_else(PoisonError::into_inner).touch(key);
+        }
+    }
+
+    /// Brings the store back under `max_keys` after a write that may have
+    /// added several keys.
+    fn enforce_max_keys(&mut self) -> Result<()> {
+        match self.max_keys {
+            Some(max_keys) => self.evict_down_to(max_keys),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes least recently used keys until at most `limit` are left.
+    fn evict_down_to(&mut self, limit: usize) -> Result<()> {
+        while self.index.len() > limit {
+            let oldest = match &self.recency {
+                Some(recency) => recency.lock().unwrap_or_else(PoisonError::into_inner).oldest().map(str::to_string),
+                None => None,
+            };
+            let Some(key) = oldest else {
+                break;
+            };
+
+            // Part of a write that already passed `apply_backpressure`.
+            let record = self.encode(&Command::Remove { key: key.clone() })?;
+            let ptr = self.append_encoded(&record)?;
+            self.index_remove(&key, ptr);
+        }
+        Ok(())
+    }
+
+    fn bloom_insert(&mut self, key: &str) {
+        if let Some(bloom) = &mut self.bloom {
+            if bloom.is_saturated() {
+                *bloom = BloomFilter::from_keys(self.index.keys());
+            }
+            bloom.insert(key);
+        }
+    }
+
+    /// Rewrites the key of `cmd` into its normalized form.
+    fn normalize_command(&self, cmd: Command) -> Command {
+        match cmd {
+            Command::Set { key, val, version, nonce, written_at, schema } => Command::Set {
+                key: normalize_owned(&*self.normalizer, key),
+                val,
+                version,
+                nonce,
+                written_at,
+                schema,
+            },
+            Command::Remove { key } => Command::Remove {
+                key: normalize_owned(&*self.normalizer, key),
+            },
+            marker => marker,
+        }
+    }
+
+    /// Validates `key` as it would be stored, i.e. after normalization.
+    #[cfg(feature = "http")]
+    pub(crate) fn check_key(&self, key: &str) -> Result<()> {
+        self.validate_key(&self.normalizer.normalize(key))
+    }
+
+    /// Applies the built-in empty-key check, then `KvStoreOptions::key_validator`.
+    fn validate_key(&self, key: &str) -> Result<()> {
+        if key.is_empty() {
+            return Err(KvError::InvalidKey("Key cannot be empty".to_string()));
+        }
+        if let Some(validator) = &self.validator {
+            validator.check(key)?;
+        }
+        Ok(())
+    }
+
+    /// Timestamp for `Command::Set::written_at`: now by the store's clock.
+    fn write_time(&self) -> Option<u64> {
+        let since_epoch = self.clock.now().duration_since(UNIX_EPOCH).ok()?;
+        Some(since_epoch.as_millis() as u64)
+    }
+
+    /// `cmd` as it goes into the log: with its value encrypted if the store
+    /// has a key and the value isn't encrypted already (e.g. by `apply`).
+    fn seal<'c>(&self, cmd: &'c Command) -> Result<Cow<'c, Command>> {
+        match (cmd, &self.cipher) {
+            (Command::Set { key, val, version, nonce: None, written_at, schema }, Some(cipher)) => {
+                let (val, nonce) = cipher.encrypt(val)?;
+                Ok(Cow::Owned(Command::Set {
+                    key: key.clone(),
+                    val,
+                    version: *version,
+                    nonce: Some(nonce),
+                    written_at: *written_at,
+                    schema: *schema,
+                }))
+            }
+            _ => Ok(Cow::Borrowed(cmd)),
+        }
+    }
+
+    fn append_command(&mut self, cmd: &Command) -> Result<LogPointer> {
+        self.apply_backpressure()?;
+        let record = self.encode(cmd)?;
+        self.append_encoded(&record)
+    }
+
+    /// `cmd` as a log record: sealed, serialized and newline-terminated.
+    /// Nothing is written yet, so a failure here leaves the store untouched.
+    fn encode(&self, cmd: &Command) -> Result<Vec<u8>> {
+        let mut record = serde_json::to_vec(&self.seal(cmd)?)?;
+        record.push(b'\n');
+        Ok(record)
+    }
+
+    /// Appends a record from `encode`, returning where it landed.
+    fn append_encoded(&mut self, record: &[u8]) -> Result<LogPointer> {
+        let offset = self.writer.stream_position()?;
+        self.write_records(offset, record)?;
+        Ok(LogPointer { offset, len: record.len() as u64 })
+    }
+
+    /// Appends `cmds` back to back with a single flush, returning each record's pointer.
+    /// Everything is serialized before the first byte is written.
+    fn append_batch(&mut self, cmds: &[Command]) -> Result<Vec<LogPointer>> {
+        self.apply_backpressure()?;
+        let start_offset = self.writer.stream_position()?;
+        let mut offset = start_offset;
+        let mut buf = Vec::new();
+        let mut ptrs = Vec::with_capacity(cmds.len());
+
+        for cmd in cmds {
+            let start = buf.len();
+            serde_json::to_writer(&mut buf, &self.seal(cmd)?)?;
+            buf.push(b'\n');
+
+            let len = (buf.len() - start) as u64;
+            ptrs.push(LogPointer { offset, len });
+            offset += len;
+        }
+
+        self.write_records(start_offset, &buf)?;
+        Ok(ptrs)
+    }
+
+    /// Writes and flushes `bytes` at the end of the log, which starts at
+    /// `offset`, then checks the log grew by exactly that much. On any failure
+    /// the log is truncated back to `offset`, so no partial record is left
+    /// for a later append to land behind.
+    fn write_records(&mut self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let result = self.writer.write_all(bytes).and_then(|()| self.writer.flush());
+        let expected = bytes.len() as u64;
+        let written = self.writer.get_ref().file.metadata()?.len().saturating_sub(offset);
+        if result.is_ok() && written == expected {
+            self.log_len = offset + expected;
+            if self.sync_policy == SyncPolicy::Always {
+                self.storage.sync(&self.writer.get_ref().file, self.sync_method)?;
+            }
+            return Ok(());
+        }
+
+        // Swap in a fresh writer first: dropping the old one would try to
+        // flush whatever it still buffers.
+        let failed = std::mem::replace(&mut self.writer, open_writer(&self.log_path, self.file_mode, &self.storage)?);
+        let (log, _unwritten) = failed.into_parts();
+        log.file.set_len(offset)?;
+        self.writer.seek(SeekFrom::End(0))?;
+
+        result?;
+        Err(KvError::ShortWrite { expected, written })
+    }
+
+    fn maybe_compact(&mut self) -> Result<()> {
+        if self.inline_compaction {
+            self.compact_if_due()?;
+        }
+        Ok(())
+    }
+
+    /// Leaves compaction to a `StoreManager` or `CompactionScheduler` instead of the writes.
+    pub(crate) fn disable_inline_compaction(&mut self) {
+        self.inline_compaction = false;
+    }
+
+    /// Whether the log has expired or enough of it is stale that a write
+    /// would compact it, were compaction left to the writes. For code that
+    /// compacts on its own schedule instead.
+    pub fn needs_compaction(&self) -> bool {
+        if self.log_expired() || self.over_ceiling() {
+            return true;
+        }
+        if self.uncompacted > self.threshold {
+            let reclaimable = self.reclaimable_bytes();
+            return reclaimable > self.threshold && reclaimable >= self.log_len / MIN_RECLAIM_DIVISOR;
+        }
+        false
+    }
+
+    /// Compacts if `needs_compaction`; returns whether it did.
+    fn compact_if_due(&mut self) -> Result<bool> {
+        if self.needs_compaction() {
+            self.compact()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Whether reclaimable stale data has passed `uncompacted_ceiling`.
+    fn over_ceiling(&self) -> bool {
+        self.ceiling
+            .is_some_and(|ceiling| self.uncompacted > ceiling && self.reclaimable_bytes() > ceiling)
+    }
+
+    /// Holds a write back while the store is over `uncompacted_ceiling`:
+    /// compacts first, or refuses the write if compaction is left to a
+    /// `StoreManager` or the caller.
+    fn apply_backpressure(&mut self) -> Result<()> {
+        if !self.over_ceiling() {
+            return Ok(());
+        }
+        if self.inline_compaction {
+            self.compact()?;
+            return Ok(());
+        }
+        Err(KvError::Backpressure {
+            uncompacted: self.uncompacted,
+            ceiling: self.ceiling.unwrap_or_default(),
+        })
+    }
+
+    /// Whether the active log has outlived `max_log_age` and should be rotated.
+    fn log_expired(&self) -> bool {
+        match self.max_log_age {
+            Some(max_age) => self
+                .clock
+                .now()
+                .duration_since(self.log_created)
+                .is_ok_and(|age| age >= max_age),
+            None => false,
+        }
+    }
+
+    /// Renames the compacted log at `compact_path` over the log. A rename
+    /// can't cross filesystems (`EXDEV`), so then the file is copied next to
+    /// the log, synced and renamed from there: the log is still replaced
+    /// atomically, never left half-copied.
+    fn move_into_place(&self, compact_path: &Path) -> io::Result<()> {
+        match self.storage.rename(compact_path, &self.log_path) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let local_path = self.dir_path.join(COMPACT_FILE);
+                let copied = std::fs::copy(compact_path, &local_path)
+                    .and_then(|_| self.storage.sync(&File::open(&local_path)?, SyncMethod::All))
+                    .and_then(|()| self.storage.rename(&local_path, &self.log_path));
+                if copied.is_err() {
+                    let _ = std::fs::remove_file(&local_path);
+                }
+                copied?;
+                std::fs::remove_file(compact_path)
+            }
+            result => result,
+        }
+    }
+
+    /// Projects what `compact` would reclaim from the store's bookkeeping of
+    /// stale bytes, without touching the log. On a store from
+    /// `open_without_index` nothing is known to be stale until the first write.
+    pub fn estimate_compaction(&self) -> CompactionEstimate {
+        let stale = self.reclaimable_bytes().min(self.log_len);
+        // The temp dir is only created by the first compaction.
+        let build_dir = self.compaction_temp_dir.as_deref().unwrap_or(&self.dir_path);
+        CompactionEstimate {
+            log_bytes: self.log_len,
+            projected_bytes: self.log_len - stale,
+            stale_bytes: self.uncompacted.min(self.log_len),
+            free_bytes: build_dir.ancestors().find_map(disk::free_space),
+        }
+    }
+
+    /// Stale bytes compaction would actually drop: all of `uncompacted`
+    /// except tombstones it keeps for `tombstone_grace`.
+    fn reclaimable_bytes(&self) -> u64 {
+        let retained: u64 = self.retained_tombstones().map(|(_, t)| t.ptr.len).sum();
+        self.uncompacted.saturating_sub(retained)
+    }
+
+    /// Tombstones still within `tombstone_grace`, which compaction keeps.
+    /// Checked on every write, so it borrows rather than collects.
+    fn retained_tombstones(&self) -> impl Iterator<Item = (&String, &Tombstone)> + '_ {
+        let grace = self.tombstone_grace;
+        let now = grace.map(|_| self.clock.now());
+        self.tombstones.iter().filter(move |(_, t)| match (grace, now) {
+            (Some(grace), Some(now)) => now.duration_since(t.removed_at).map_or(true, |age| age < grace),
+            _ => false,
+        })
+    }
+
+    /// Rewrites the log with only live data, whatever the threshold. Normally
+    /// writes trigger this on their own; call it to reclaim space on demand.
+    /// If interrupted, the log is left as it was and the partial copy is
+    /// removed at the next `open`; compaction then starts over.
+    pub fn compact(&mut self) -> Result<CompactionReport> {
+        let plan = self.plan_compaction(COMPACT_FILE)?;
+        let built = plan.build()?;
+        self.install_compaction(built)
+    }
+
+    /// Starts a compaction to be built off the write path, by a
+    /// `CompactionScheduler` or `StoreManager`. `None` if one is already
+    /// being built.
+    pub(crate) fn plan_background_compaction(&mut self) -> Result<Option<CompactionPlan>> {
+        if self.building.swap(true, Ordering::AcqRel) {
+            return Ok(None);
+        }
+        let guard = BuildGuard(Arc::clone(&self.building));
+        let mut plan = self.plan_compaction(BACKGROUND_COMPACT_FILE)?;
+        plan.guard = Some(guard);
+        Ok(Some(plan))
+    }
+
+    /// What to copy into a compacted log built in `file_name`: the live
+    /// records and retained tombstones as of now.
+    fn plan_compaction(&mut self, file_name: &str) -> Result<CompactionPlan> {
+        self.ensure_index()?;
+        let compact_path = match &self.compaction_temp_dir {
+            // Named after the process and the data directory, so stores
+            // sharing a temp dir don't overwrite each other's files.
+            Some(temp_dir) => {
+                std::fs::create_dir_all(temp_dir).map_err(|e| KvError::CompactionFailed(e.to_string()))?;
+                temp_dir.join(temp_compact_name(file_name, &self.dir_path))
+            }
+            None => self.dir_path.join(file_name),
+        };
+
+        // Live records are written in key order, so sequential scans of the
+        // compacted log visit keys in sorted order.
+        let mut live: Vec<(String, LogPointer)> = self.index.iter().map(|(key, ptr)| (key.clone(), *ptr)).collect();
+        live.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut tombstones: Vec<(String, LogPointer)> = self
+            .retained_tombstones()
+            .map(|(key, tombstone)| (key.clone(), tombstone.ptr))
+            .collect();
+        tombstones.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(CompactionPlan {
+            // Opened now, so a build that outlives this log still reads it.
+            log: File::open(&self.log_path)?,
+            log_len: self.log_len,
+            generation: self.generation,
+            live,
+            tombstones,
+            compact_path,
+            file_mode: self.file_mode,
+            storage: Arc::clone(&self.storage),
+            sync: self.sync_policy == SyncPolicy::Always,
+            started: Instant::now(),
+            guard: None,
+        })
+    }
+
+    /// Replaces the log with the compacted copy from `CompactionPlan::build`.
+    /// Records appended since the plan was taken are copied after it as they
+    /// are, so the swap loses no write.
+    pub(crate) fn install_compaction(&mut self, built: BuiltCompaction) -> Result<CompactionReport> {
+        let BuiltCompaction { mut log, mut file, plan_len, generation, compact_path, index, tombstones, len, started, .. } =
+            built;
+        if generation != self.generation {
+            let _ = std::fs::remove_file(&compact_path);
+            return Err(KvError::CompactionFailed("the log was compacted while this compaction was built".to_string()));
+        }
+        let bytes_before = self.log_len;
+
+        log.seek(SeekFrom::Start(plan_len))?;
+        let tail = io::copy(&mut log.take(self.log_len - plan_len), &mut file)?;
+        if self.sync_policy == SyncPolicy::Always {
+            // The rename must not become durable before the data it points at.
+            self.storage.sync(&file.file, SyncMethod::All)?;
+        }
+        drop(file);
+        let pos = len + tail;
+        let moved = |ptr: LogPointer| LogPointer { offset: ptr.offset - plan_len + len, len: ptr.len };
+
+        // Keys written since the plan point into the tail; the rest are where
+        // the build put them, unless they have been removed.
+        let mut new_index = index;
+        new_index.retain(|key, _| self.index.contains_key(key));
+        for (key, ptr) in &self.index {
+            if ptr.offset >= plan_len {
+                new_index.insert(key.clone(), moved(*ptr));
+            }
+        }
+        let new_tombstones: HashMap<String, Tombstone> = self
+            .tombstones
+            .iter()
+            .filter_map(|(key, tombstone)| {
+                let ptr = if tombstone.ptr.offset >= plan_len {
+                    moved(tombstone.ptr)
+                } else {
+                    *tombstones.get(key)?
+                };
+                Some((key.clone(), Tombstone { ptr, ..*tombstone }))
+            })
+            .collect();
+        let live_bytes: u64 = new_index.values().map(|ptr| ptr.len).sum();
+
+        // The old hint goes first, so a crash can't leave it describing the new log.
+        let hint_path = self.dir_path.join(HINT_FILE);
+        match std::fs::remove_file(&hint_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(KvError::CompactionFailed(e.to_string())),
+        }
+
+        if let Err(e) = self.move_into_place(&compact_path) {
+            // The old log is intact; don't leave the compacted copy behind.
+            let _ = std::fs::remove_file(&compact_path);
+            return Err(KvError::CompactionFailed(e.to_string()));
+        }
+        if self.sync_policy == SyncPolicy::Always {
+            self.storage.sync_dir(&self.dir_path)?;
+        }
+
+        self.writer = open_writer(&self.log_path, self.file_mode, &self.storage)?;
+        self.log_created = self.clock.now();
+        self.generation += 1;
+        if let Some(flusher) = &self.flusher {
+            flusher.set_log(self.writer.get_ref().file.try_clone()?);
+        }
+        
+        self.index = new_index;
+        self.tombstones = new_tombstones;
+        // Retained tombstones are stale like any other, just not yet reclaimable.
+        self.uncompacted = pos - live_bytes;
+        self.log_len = pos;
+
+        // Only an optimization for `quick_stats`; the store works without it.
+        if let Err(e) = hint::write_hint(&hint_path, pos, &self.index, self.hint_format, self.file_mode) {
+            eprintln!("Warning: failed to write hint file: {}", e);
+        }
+
+        // Compaction is the one point where removed keys can be cleared from the filter.
+        if self.bloom.is_some() {
+            self.bloom = Some(BloomFilter::from_keys(self.index.keys()));
+        }
+
+        let finished = self.clock.now();
+        self.counters.add_compaction(finished);
+
+        // Written last, so directory watchers can take it as the signal that
+        // the new log is in place.
+        if let Err(e) = write_compacted_marker(&self.dir_path.join(COMPACTED_FILE), finished, self.file_mode) {
+            eprintln!("Warning: failed to write compaction marker: {}", e);
+        }
+
+        Ok(CompactionReport {
+            bytes_before,
+            bytes_after: pos,
+            entries_retained: self.index.len(),
+            duration: started.elapsed(),
+        })
+    }
+
+    /// Rewrites the log with live records in sorted key order, even if
+    /// nothing is stale. Writes after a compaction land in arrival order, so
+    /// over time sequential scans (`scan`, `get_many_prefixed`) seek back and
+    /// forth; this restores their locality. It is the same rewrite as
+    /// `compact`, for when layout rather than space is the reason.
+    pub fn defragment(&mut self) -> Result<()> {
+        self.compact()?;
+        Ok(())
+    }
+}
+
+/// A compaction taken from the store by `plan_compaction`: what to copy and
+/// where to. Built by `build` without the store, then installed by
+/// `install_compaction`.
+pub(crate) struct CompactionPlan {
+    /// The log, opened before a later compaction could replace it.
+    log: File,
+    /// Length of the log when planned; anything after it is copied at install.
+    log_len: u64,
+    generation: u64,
+    /// Live keys in sorted order, with their records in the log.
+    live: Vec<(String, LogPointer)>,
+    /// Tombstones to carry over, in sorted order.
+    tombstones: Vec<(String, LogPointer)>,
+    compact_path: PathBuf,
+    file_mode: Option<u32>,
+    /// Writes and syncs the compacted log, as it does the log.
+    storage: Arc<dyn Storage>,
+    /// Whether to sync the compacted log once built, under `SyncPolicy::Always`.
+    sync: bool,
+    started: Instant,
+    /// Held from plan to install by a compaction built off the write path.
+    guard: Option<BuildGuard>,
+}
+
+impl CompactionPlan {
+    /// Copies the planned records into the compacted log. Needs nothing from
+    /// the store, so a background compaction runs this while writers carry on.
+    pub(crate) fn build(self) -> Result<BuiltCompaction> {
+        let CompactionPlan {
+            log,
+            log_len,
+            generation,
+            live,
+            tombstones,
+            compact_path,
+            file_mode,
+            storage,
+            sync,
+            started,
+            guard,
+        } = self;
+        let mut tmp_writer = BufWriter::new(LogFile::new(
+            create_file(&compact_path, file_mode).map_err(|e| KvError::CompactionFailed(e.to_string()))?,
+            Arc::clone(&storage),
+        ));
+
+        let mut index = HashMap::with_capacity(live.len());
+        let mut reader = BufReader::new(&log);
+        let mut pos = 0u64;
+
+        for (key, ptr) in live {
+            reader.seek(SeekFrom::Start(ptr.offset))?;
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            
+            let len = line.len() as u64;
+            tmp_writer.write_all(line.as_bytes())?;
+            index.insert(key, LogPointer { offset: pos, len });
+            pos += len;
+        }
+
+        // Tombstones are normally dropped: with the old records gone there is
+        // nothing left for them to hide. Within `tombstone_grace` of the
+        // removal they are copied after the live records instead, so readers
+        // of the log (replicas, merges) still learn of the removal.
+        let mut new_tombstones = HashMap::with_capacity(tombstones.len());
+        for (key, ptr) in tombstones {
+            reader.seek(SeekFrom::Start(ptr.offset))?;
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+
+            let len = line.len() as u64;
+            tmp_writer.write_all(line.as_bytes())?;
+            new_tombstones.insert(key, LogPointer { offset: pos, len });
+            pos += len;
+        }
+        drop(reader);
+
+        tmp_writer.flush()?;
+        let file = tmp_writer.into_inner().map_err(io::IntoInnerError::into_error)?;
+        if sync {
+            storage.sync(&file.file, SyncMethod::All)?;
+        }
+
+        Ok(BuiltCompaction {
+            log,
+            file,
+            plan_len: log_len,
+            generation,
+            compact_path,
+            index,
+            tombstones: new_tombstones,
+            len: pos,
+            started,
+            _guard: guard,
+        })
+    }
+}
+
+/// The compacted log written by `CompactionPlan::build`, waiting for
+/// `install_compaction` to add the tail and swap it in.
+pub(crate) struct BuiltCompaction {
+    log: File,
+    file: LogFile,
+    plan_len: u64,
+    generation: u64,
+    compact_path: PathBuf,
+    index: HashMap<String, LogPointer>,
+    /// Where each carried-over tombstone now is.
+    tombstones: HashMap<String, LogPointer>,
+    /// Bytes written so far.
+    len: u64,
+    started: Instant,
+    _guard: Option<BuildGuard>,
+}
+
+/// Marks a compaction as being built off the write path until dropped, so a
+/// second can't start building into the same file.
+struct BuildGuard(Arc<AtomicBool>);
+
+impl Drop for BuildGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A `Set` record of a key, as read back from the log.
+struct StoredValue {
+    val: String,
+    version: Option<u64>,
+    written_at: Option<u64>,
+    schema: Option<u32>,
+}
+
+impl fmt::Debug for KvStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvStore")
+            .field("dir_path", &self.dir_path)
+            .field("keys", &self.index.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Outcome of replaying the log: the index it describes and the byte counts
+/// that determine how much compaction would reclaim.
+struct Replay {
+    index: HashMap<String, LogPointer>,
+    total_bytes: u64,
+    live_bytes: u64,
+    /// Offset of a transaction at the tail whose `Commit` marker is missing.
+    uncommitted: Option<u64>,
+    /// Offset of a final record cut short by a crash (unparseable, no newline).
+    torn_tail: Option<u64>,
+    /// Offsets of unparseable records that were skipped.
+    skipped: Vec<u64>,
+    /// Offsets of transactions discarded as unterminated or damaged.
+    discarded: Vec<u64>,
+    /// Bytes of `Set`/`Remove` records replayed.
+    recovered_bytes: u64,
+    /// Latest `Remove` of each key that wasn't set again afterwards.
+    tombstones: HashMap<String, LogPointer>,
+}
+
+/// Replays the log at `path`, indexing only keys for which `wanted` is true.
+/// Keys are normalized before they are checked or indexed. Records of a
+/// transaction apply only once its `Commit` marker is read.
+fn replay_log(path: &Path, normalizer: &dyn KeyNormalizer, wanted: impl Fn(&str) -> bool) -> Result<Replay> {
+    replay_log_from(path, 0, 0, normalizer, wanted)
+}
+
+/// Like `replay_log`, starting at the record at `start` rather than the
+/// beginning, with room in the index for `capacity` keys up front.
+fn replay_log_from(
+    path: &Path,
+    start: u64,
+    capacity: usize,
+    normalizer: &dyn KeyNormalizer,
+    wanted: impl Fn(&str) -> bool,
+) -> Result<Replay> {
+    let mut replay = Replay {
+        index: HashMap::with_capacity(capacity),
+        total_bytes: 0,
+        live_bytes: 0,
+        uncommitted: None,
+        torn_tail: None,
+        skipped: Vec::new(),
+        discarded: Vec::new(),
+        recovered_bytes: 0,
+        tombstones: HashMap::new(),
+    };
+    // Records of an open transaction (with the offset of its `Begin`),
+    // applied only once the matching `Commit` is read. Records outside a
+    // transaction are implicit single-record batches and apply immediately.
+    let mut batch: Option<(u64, Vec<(LogPointer, Command)>)> = None;
+
+    // The last record read, if it failed to parse.
+    let mut last_corrupt = None;
+
+    for record in LogIter::open_at(path, start)? {
+        let (ptr, parsed) = record?;
+        replay.total_bytes += ptr.len;
+        last_corrupt = parsed.is_err().then_some(ptr);
+
+        match parsed {
+            Ok(Command::Begin) => {
+                if let Some((offset, _)) = batch.replace((ptr.offset, Vec::new())) {
+                    eprintln!("Warning: discarding unterminated transaction at offset {}", offset);
+                    replay.discarded.push(offset);
+                }
+            }
+            Ok(Command::Commit { count }) => match batch.take() {
+                Some((_, records)) if records.len() as u64 == count => {
+                    for (ptr, cmd) in records {
+                        replay.apply(ptr, cmd, normalizer, &wanted);
+                    }
+                }
+                Some((offset, records)) => {
+                    eprintln!(
+                        "Warning: discarding damaged transaction at offset {} ({} of {} records readable)",
+                        offset, records.len(), count
+                    );
+                    replay.discarded.push(offset);
+                }
+                None => {
+                    eprintln!("Warning: ignoring commit marker without a transaction at offset {}", ptr.offset);
+                }
+            },
+            Ok(cmd) => match &mut batch {
+                Some((_, records)) => records.push((ptr, cmd)),
+                None => replay.apply(ptr, cmd, normalizer, &wanted),
+            },
+            Err(e) => {
+                eprintln!("Warning: skipping corrupted log entry at offset {}: {}", ptr.offset, e);
+                replay.skipped.push(ptr.offset);
+            }
+        }
+    }
+
+    replay.uncommitted = batch.map(|(offset, _)| offset);
+    if let Some(ptr) = last_corrupt {
+        // Every complete record ends in a newline; one without is a torn write.
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(ptr.offset + ptr.len - 1))?;
+        let mut last_byte = [0u8];
+        io::Read::read_exact(&mut file, &mut last_byte)?;
+        if last_byte[0] != b'\n' {
+            replay.torn_tail = Some(ptr.offset);
+        }
+    }
+    Ok(replay)
+}
+
+impl Replay {
+    /// Applies one committed record to the index.
+    fn apply(&mut self, ptr: LogPointer, cmd: Command, normalizer: &dyn KeyNormalizer, wanted: impl Fn(&str) -> bool) {
+        let (key, is_set) = match cmd {
+            Command::Set { key, .. } => (key, true),
+            Command::Remove { key } => (key, false),
+            Command::Begin | Command::Commit { .. } => return,
+        };
+        let key = normalize_owned(normalizer, key);
+        if !wanted(&key) {
+            return;
+        }
+
+        self.recovered_bytes += ptr.len;
+        if is_set {
+            self.live_bytes += ptr.len;
+            self.tombstones.remove(&key);
+            if let Some(old_ptr) = self.index.insert(key, ptr) {
+                self.live_bytes -= old_ptr.len;
+            }
+        } else {
+            if let Some(old_ptr) = self.index.remove(&key) {
+                self.live_bytes -= old_ptr.len;
+            }
+            self.tombstones.insert(key, ptr);
+        }
+    }
+}
+
+/// File name for a compacted log built in a shared temp dir, unique to this
+/// process and data directory.
+fn temp_compact_name(file_name: &str, dir_path: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    dir_path.hash(&mut hasher);
+    format!("{}.{}.{:016x}", file_name, std::process::id(), hasher.finish())
+}
+
+/// The threshold saved by `set_compaction_threshold`, if any. An unreadable
+/// `store.meta` only costs the setting, not the open.
+fn persisted_threshold(dir_path: &Path) -> Option<u64> {
+    match meta::read_meta(&dir_path.join(META_FILE)) {
+        Ok(meta) => meta.and_then(|meta| meta.compaction_threshold),
+        Err(e) => {
+            eprintln!("Warning: ignoring unreadable {}: {}", META_FILE, e);
+            None
+        }
+    }
+}
+
+/// Whether `text` matches the glob `pattern` (see `KvStore::scan_glob`).
+/// On a mismatch the last `*` takes one more character and matching resumes
+/// after it. Earlier stars never need revisiting, so this takes at most
+/// O(pattern length × text length) steps.
+fn glob_match(pattern: &[char], text: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Pattern position after the last `*`, and the text position it resumes from.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((after_star, resume)) => {
+                    p = after_star;
+                    t = resume + 1;
+                    backtrack = Some((after_star, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// `e`, or `KvError::ReadOnlyFilesystem` if it is `EROFS` from opening the
+/// store in `dir_path` (e.g. a mounted snapshot), which a raw I/O error
+/// would leave unexplained.
+fn read_only_error(e: KvError, dir_path: &Path) -> KvError {
+    match e {
+        KvError::Io(e) if e.kind() == io::ErrorKind::ReadOnlyFilesystem => KvError::ReadOnlyFilesystem(dir_path.to_path_buf()),
+        e => e,
+    }
+}
+
+/// Reads and parses the record at `ptr`.
+fn read_record_from(reader: &mut BufReader<File>, ptr: &LogPointer) -> Result<Command> {
+    reader.seek(SeekFrom::Start(ptr.offset))?;
+    
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    
+    parse_record(&line, ptr)
+}
+
+/// Parses the record read at `ptr`; anything unparsable there is corruption.
+fn parse_record(line: &str, ptr: &LogPointer) -> Result<Command> {
+    serde_json::from_str(line.trim()).map_err(|_| KvError::LogCorruption(ptr.offset))
+}
+
+/// Creates (or truncates) a file for writing, with permission bits `mode`
+/// if it is new; see `KvStoreOptions::file_mode`.
+pub(crate) fn create_file(path: &Path, mode: Option<u32>) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    set_mode(&mut options, mode);
+    options.open(path)
+}
+
+fn set_mode(options: &mut OpenOptions, mode: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::OpenOptionsExt::mode(options, mode);
+    }
+    #[cfg(not(unix))]
+    let _ = (options, mode);
+}
+
+/// Rewrites `store.compacted` with `finished`, in milliseconds since the Unix epoch.
+fn write_compacted_marker(path: &Path, finished: SystemTime, mode: Option<u32>) -> io::Result<()> {
+    let millis = finished.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    create_file(path, mode)?.write_all(format!("{}\n", millis).as_bytes())
+}
+
+/// Opens the log for appending, positioned at its end. Append mode alone only
+/// moves the cursor on the first write, which would make `stream_position`
+/// report offset 0 for the first record written after `open` or compaction.
+fn open_writer(path: &Path, mode: Option<u32>, storage: &Arc<dyn Storage>) -> Result<BufWriter<LogFile>> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    set_mode(&mut options, mode);
+    let mut writer = BufWriter::new(LogFile::new(storage.open_log(path, &options)?, Arc::clone(storage)));
+    writer.seek(SeekFrom::End(0))?;
+    Ok(writer)
+}
+
+/// Reads the log file at `path` as a stream of `(offset, Command)` pairs.
+/// This is a static reader: it builds no index and takes no locks, so it can
+/// be used by external tools while a store is open. Unparseable records are
+/// yielded as `KvError::LogCorruption` with their offset.
+pub fn read_log(path: &Path) -> Result<impl Iterator<Item = Result<(u64, Command)>>> {
+    Ok(LogIter::open(path)?.map(|record| {
+        let (ptr, parsed) = record?;
+        parsed
+            .map(|cmd| (ptr.offset, cmd))
+            .map_err(|_| KvError::LogCorruption(ptr.offset))
+    }))
+}
+
+/// Sequential scan over the raw log, yielding each record's location
+/// together with its parse result. Shared by `rebuild_index` and `read_log`.
+struct LogIter {
+    reader: BufReader<File>,
+    pos: u64,
+    line: String,
+    done: bool,
+}
+
+impl LogIter {
+    fn open(path: &Path) -> Result<LogIter> {
+        Self::open_at(path, 0)
+    }
+
+    /// Starts at `offset`, which must be the start of a record.
+    fn open_at(path: &Path, offset: u64) -> Result<LogIter> {
+        let mut reader = BufReader::new(File::open(path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(LogIter {
+            reader,
+            pos: offset,
+            line: String::new(),
+            done: false,
+        })
+    }
+}
+
+impl Iterator for LogIter {
+    type Item = Result<(LogPointer, serde_json::Result<Command>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.line.clear();
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(n) => {
+                let ptr = LogPointer { offset: self.pos, len: n as u64 };
+                self.pos += n as u64;
+                // A record is only complete with its newline; without it the
+                // write was cut short, however much of it happens to parse.
+                let parsed = if self.line.ends_with('\n') {
+                    serde_json::from_str(self.line.trim())
+                } else {
+                    Err(serde_json::Error::io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "record is missing its trailing newline",
+                    )))
+                };
+                Some(Ok((ptr, parsed)))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
     }
 }
@@ -0,0 +1,61 @@
+use crate::engine::KvStore;
+use crate::error::Result;
+
+/// A key and its current value, for read-then-write updates; created by
+/// `KvStore::entry`, which does the one read.
+///
+/// Each method writes at most once and only in its own case: `and_modify`
+/// when the key is present, the `or_insert` family when it is absent. So
+/// `store.entry(key)?.and_modify(f)?.or_insert(default)?` is a single read
+/// and a single write whichever way it goes.
+pub struct Entry<'a> {
+    store: &'a mut KvStore,
+    key: String,
+    value: Option<String>,
+}
+
+impl<'a> Entry<'a> {
+    pub(crate) fn new(store: &'a mut KvStore, key: String, value: Option<String>) -> Entry<'a> {
+        Entry { store, key, value }
+    }
+
+    /// The key, normalized.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The value as it stands, including any `and_modify` already applied.
+    pub fn get(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// If the key is present, transforms its value in place and writes the
+    /// result. Absent keys are left alone.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut String)) -> Result<Entry<'a>> {
+        if let Some(value) = &mut self.value {
+            let before = value.clone();
+            f(value);
+            if !(self.store.skips_unchanged() && *value == before) {
+                self.store.write_set(self.key.clone(), value.clone())?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// The value, after first setting it to `default` if the key is absent.
+    pub fn or_insert(self, default: String) -> Result<String> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Like `or_insert`, computing the default only if it is needed.
+    pub fn or_insert_with(self, default: impl FnOnce() -> String) -> Result<String> {
+        match self.value {
+            Some(value) => Ok(value),
+            None => {
+                let value = default();
+                self.store.write_set(self.key, value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -15,11 +16,35 @@ pub enum KvError {
     #[error("Invalid key: {0}")]
     InvalidKey(String),
 
+    #[error("Invalid store name: {0:?}")]
+    InvalidStoreName(String),
+
+    #[error("Invalid data directory: {} holds no store", .0.display())]
+    InvalidDataDir(PathBuf),
+
     #[error("Log corruption detected at offset {0}")]
     LogCorruption(u64),
 
+    #[error("Decryption failed: {0}")]
+    Decryption(String),
+
+    #[error("Index describes a log of {index_len} bytes, but the log is {log_len} bytes")]
+    StaleIndex { index_len: u64, log_len: u64 },
+
+    #[error("Write refused: {uncompacted} stale bytes are over the ceiling of {ceiling}; retry after compaction")]
+    Backpressure { uncompacted: u64, ceiling: u64 },
+
+    #[error("{} is on a read-only filesystem; opening a store needs write access (engine::read_log and KvStore::quick_stats only read)", .0.display())]
+    ReadOnlyFilesystem(PathBuf),
+
     #[error("Compaction failed: {0}")]
     CompactionFailed(String),
+
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+
+    #[error("Short write: expected {expected} bytes, wrote {written}")]
+    ShortWrite { expected: u64, written: u64 },
 }
 
 pub type Result<T> = std::result::Result<T, KvError>;
@@ -0,0 +1,62 @@
+use std::{
+    fs::File,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::options::SyncMethod;
+use crate::storage::Storage;
+
+/// Background thread that syncs the log every `interval`, bounding how long
+/// an acknowledged write can sit in the OS page cache. Stopped on drop.
+pub(crate) struct Flusher {
+    shared: Arc<(Mutex<State>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct State {
+    /// Handle on the active log; replaced when compaction swaps the file.
+    log: File,
+    storage: Arc<dyn Storage>,
+    method: SyncMethod,
+    stopped: bool,
+}
+
+impl Flusher {
+    pub(crate) fn start(log: File, storage: Arc<dyn Storage>, method: SyncMethod, interval: Duration) -> Flusher {
+        let shared = Arc::new((Mutex::new(State { log, storage, method, stopped: false }), Condvar::new()));
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || run(&thread_shared, interval));
+        Flusher { shared, thread: Some(thread) }
+    }
+
+    /// Points the flusher at a new log file after compaction.
+    pub(crate) fn set_log(&self, log: File) {
+        self.shared.0.lock().unwrap_or_else(PoisonError::into_inner).log = log;
+    }
+}
+
+fn run(shared: &(Mutex<State>, Condvar), interval: Duration) {
+    let (state, wake) = shared;
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        state = wake.wait_timeout(state, interval).unwrap_or_else(PoisonError::into_inner).0;
+        if state.stopped {
+            return;
+        }
+        if let Err(e) = state.storage.sync(&state.log, state.method) {
+            eprintln!("Warning: periodic log sync failed: {}", e);
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.shared.0.lock().unwrap_or_else(PoisonError::into_inner).stopped = true;
+        self.shared.1.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
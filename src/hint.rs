@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{create_file, LogPointer};
+use crate::error::Result;
+use crate::options::HintFormat;
+
+/// Start of a binary hint file. A JSON hint starts with `{`, so the two
+/// can't be mistaken for each other.
+const BINARY_MAGIC: &[u8; 3] = b"KVH";
+/// Version byte following `BINARY_MAGIC`. Hints of other versions are ignored.
+const BINARY_VERSION: u8 = 1;
+
+/// First line of a JSON hint file: the log it describes.
+#[derive(Serialize, Deserialize)]
+struct HintHeader {
+    /// Length of the compacted log; records past it were appended later.
+    log_len: u64,
+    keys: u64,
+}
+
+/// One live key of the compacted log.
+#[derive(Serialize, Deserialize)]
+struct HintEntry {
+    key: String,
+    offset: u64,
+    len: u64,
+}
+
+/// Writes a hint file describing a freshly compacted log of `log_len` bytes,
+/// going through a temporary file so a crash never leaves half a hint.
+pub(crate) fn write_hint(
+    path: &Path,
+    log_len: u64,
+    index: &HashMap<String, LogPointer>,
+    format: HintFormat,
+    mode: Option<u32>,
+) -> Result<()> {
+    let tmp_path = path.with_extension("hint.tmp");
+    let mut writer = BufWriter::new(create_file(&tmp_path, mode)?);
+
+    match format {
+        HintFormat::Json => write_json(&mut writer, log_len, index)?,
+        HintFormat::Binary => write_binary(&mut writer, log_len, index)?,
+    }
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn write_json(writer: &mut impl Write, log_len: u64, index: &HashMap<String, LogPointer>) -> Result<()> {
+    let header = HintHeader { log_len, keys: index.len() as u64 };
+    serde_json::to_writer(&mut *writer, &header)?;
+    writer.write_all(b"\n")?;
+    for (key, ptr) in index {
+        let entry = HintEntry { key: key.clone(), offset: ptr.offset(), len: ptr.len() };
+        serde_json::to_writer(&mut *writer, &entry)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Magic and version, then the log length and key count; then per key its
+/// byte length, bytes, offset and record length. Integers are little-endian,
+/// the key length a u32 and the rest u64.
+fn write_binary(writer: &mut impl Write, log_len: u64, index: &HashMap<String, LogPointer>) -> Result<()> {
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&[BINARY_VERSION])?;
+    writer.write_all(&log_len.to_le_bytes())?;
+    writer.write_all(&(index.len() as u64).to_le_bytes())?;
+    for (key, ptr) in index {
+        let key_len = u32::try_from(key.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "key too long for a binary hint"))?;
+        writer.write_all(&key_len.to_le_bytes())?;
+        writer.write_all(key.as_bytes())?;
+        writer.write_all(&ptr.offset().to_le_bytes())?;
+        writer.write_all(&ptr.len().to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the hint file at `path`, in either format: the log length it covers,
+/// its key count and, if `with_keys`, the keys themselves. `None` if there is
+/// no hint, or it is a binary hint of a version this one doesn't know.
+pub(crate) fn read_hint(path: &Path, with_keys: bool) -> Result<Option<(u64, u64, HashSet<String>)>> {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+        read_binary(reader, with_keys)
+    } else {
+        read_json(reader, with_keys).map(Some)
+    }
+}
+
+fn read_json(mut reader: BufReader<File>, with_keys: bool) -> Result<(u64, u64, HashSet<String>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let header: HintHeader = serde_json::from_str(line.trim())?;
+
+    let mut keys = HashSet::new();
+    if with_keys {
+        keys.reserve(header.keys as usize);
+        for line in reader.lines() {
+            let entry: HintEntry = serde_json::from_str(&line?)?;
+            keys.insert(entry.key);
+        }
+    }
+    Ok((header.log_len, header.keys, keys))
+}
+
+fn read_binary(mut reader: BufReader<File>, with_keys: bool) -> Result<Option<(u64, u64, HashSet<String>)>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic[3] != BINARY_VERSION {
+        return Ok(None);
+    }
+    let log_len = read_u64(&mut reader)?;
+    let count = read_u64(&mut reader)?;
+
+    let mut keys = HashSet::new();
+    if with_keys {
+        keys.reserve(count as usize);
+        for _ in 0..count {
+            let mut key_len = [0; 4];
+            reader.read_exact(&mut key_len)?;
+            let mut key = vec![0; u32::from_le_bytes(key_len) as usize];
+            reader.read_exact(&mut key)?;
+            let key = String::from_utf8(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // The pointer: only needed by readers that rebuild an index.
+            read_u64(&mut reader)?;
+            read_u64(&mut reader)?;
+            keys.insert(key);
+        }
+    }
+    Ok(Some((log_len, count, keys)))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -1,7 +1,44 @@
+#[cfg(feature = "tokio")]
+pub mod async_store;
+mod bloom;
+mod cipher;
+mod disk;
+#[cfg(feature = "http")]
+pub mod client;
 pub mod cli;
+pub mod clock;
 pub mod cmd;
 pub mod engine;
+pub mod entry;
 pub mod error;
+mod flusher;
+mod hint;
+mod lru;
+mod meta;
+pub mod manager;
+pub mod metrics;
+pub mod normalize;
+pub mod options;
+pub mod scheduler;
+#[cfg(feature = "http")]
+pub mod server;
+pub mod snapshot;
+pub mod storage;
+pub mod store;
+pub mod txn;
 
-pub use engine::KvStore;
+#[cfg(feature = "tokio")]
+pub use async_store::AsyncKvStore;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use engine::{CompactionEstimate, CompactionReport, KvStore, LogPointer, QuickStats, RecoveryReport, SetAsideLog, SetOutcome};
+pub use entry::Entry;
 pub use error::{KvError, Result};
+pub use manager::StoreManager;
+pub use metrics::{Metrics, MetricsDelta};
+pub use normalize::{CaseFoldNormalizer, IdentityNormalizer, KeyNormalizer, TrimNormalizer};
+pub use options::{ConflictResolution, HintFormat, KvStoreOptions, SyncMethod, SyncPolicy};
+pub use scheduler::CompactionScheduler;
+pub use snapshot::Snapshot;
+pub use storage::{OsStorage, Storage};
+pub use store::Store;
+pub use txn::Txn;
@@ -0,0 +1,39 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Least-recently-used order of the live keys, for `KvStoreOptions::max_keys`.
+/// Every use stamps a key with the next tick; the smallest tick is the oldest.
+#[derive(Debug, Default)]
+pub(crate) struct Recency {
+    tick: u64,
+    ticks: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Recency {
+    /// Marks `key` as the most recently used.
+    pub(crate) fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.ticks.get_mut(key) {
+            Some(old) => {
+                self.order.remove(old);
+                *old = tick;
+            }
+            None => {
+                self.ticks.insert(key.to_string(), tick);
+            }
+        }
+        self.order.insert(tick, key.to_string());
+    }
+
+    pub(crate) fn forget(&mut self, key: &str) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// The least recently used key.
+    pub(crate) fn oldest(&self) -> Option<&str> {
+        self.order.values().next().map(String::as_str)
+    }
+}
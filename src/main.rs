@@ -1,39 +1,200 @@
 use clap::Parser;
-use kvstore::{cli::*, KvStore, Result};
+use kvstore::{cli::*, KvError, KvStore, Result};
+use std::io::{self, Read};
+use std::path::PathBuf;
 use std::process;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Process exit codes, so scripts can branch on `$?`.
+const EXIT_ERROR: i32 = 1;
+const EXIT_NOT_FOUND: i32 = 2;
+const EXIT_CORRUPTION: i32 = 3;
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        process::exit(1);
+    let cli = Cli::parse();
+    let quiet = cli.quiet;
+
+    if let Err(e) = run(cli) {
+        // --quiet leaves misses to the exit code alone
+        if !(quiet && matches!(e, KvError::KeyNotFound)) {
+            eprintln!("Error: {}", e);
+        }
+        process::exit(exit_code(&e));
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
-    let mut store = KvStore::open(&cli.data_dir)?;
+fn exit_code(e: &KvError) -> i32 {
+    match e {
+        KvError::KeyNotFound => EXIT_NOT_FOUND,
+        KvError::LogCorruption(_) => EXIT_CORRUPTION,
+        _ => EXIT_ERROR,
+    }
+}
 
-    match cli.command {
-        Commands::Set { key, value } => {
+fn run(cli: Cli) -> Result<()> {
+    let start = Instant::now();
+    let mut store = if cli.no_auto_create {
+        KvStore::open_existing(&cli.data_dir)?
+    } else {
+        KvStore::open(&cli.data_dir)?
+    };
+    if cli.verbose {
+        eprintln!("Opened {} in {:?}", cli.data_dir.display(), start.elapsed());
+    }
+
+    let start = Instant::now();
+    let found = match cli.command {
+        Commands::Set { key, value, value_file, value_stdin } => {
+            let value = read_value(value, value_file, value_stdin)?;
             store.set(key, value)?;
             // Silent success (matches Redis/memcached behavior)
+            true
         }
         
-        Commands::Get { key } => {
-            match store.get(&key)? {
-                Some(value) => println!("{}", value),
-                None => {
-                    eprintln!("Key not found");
-                    process::exit(1);
+        Commands::Get { key, default } => {
+            match store.get(&key)?.or(default) {
+                Some(value) => {
+                    println!("{}", value);
+                    true
                 }
+                None => false,
             }
         }
         
         Commands::Rm { key } => {
             store.remove(key)?;
             // Silent success
+            true
+        }
+
+        Commands::Compact { dry_run: true } => {
+            let estimate = store.estimate_compaction();
+            println!(
+                "Would compact {} -> {} bytes ({} freed, {:.0}% stale)",
+                estimate.log_bytes(),
+                estimate.projected_bytes(),
+                estimate.bytes_saved(),
+                estimate.fragmentation_ratio() * 100.0
+            );
+            if let Some(free) = estimate.free_bytes() {
+                println!("{} bytes free for the compacted copy", free);
+            }
+            true
+        }
+
+        Commands::Compact { dry_run: false } => {
+            let report = store.compact()?;
+            println!(
+                "Compacted {} -> {} bytes ({} freed, {} keys) in {:?}",
+                report.bytes_before(),
+                report.bytes_after(),
+                report.bytes_freed(),
+                report.entries_retained(),
+                report.duration()
+            );
+            true
+        }
+
+        Commands::Stats { output: OutputFormat::Text } => {
+            let metrics = store.metrics();
+            println!("keys: {}", store.len()?);
+            println!("log bytes: {}", store.estimate_compaction().log_bytes());
+            println!("stale bytes: {}", store.uncompacted_bytes());
+            println!("gets: {}", metrics.gets());
+            println!("sets: {}", metrics.sets());
+            println!("removes: {}", metrics.removes());
+            println!("compactions: {}", metrics.compactions());
+            true
+        }
+
+        Commands::Stats { output: OutputFormat::Json } => {
+            println!(
+                "{{\"keys\":{},\"log_bytes\":{},\"stale_bytes\":{},\"metrics\":{}}}",
+                store.len()?,
+                store.estimate_compaction().log_bytes(),
+                store.uncompacted_bytes(),
+                store.metrics_json()
+            );
+            true
+        }
+
+        #[cfg(feature = "http")]
+        Commands::Serve { http } => {
+            let server = Arc::new(kvstore::server::Server::bind(http.as_str(), store)?);
+            #[cfg(unix)]
+            shutdown_on_signal(Arc::clone(&server));
+            eprintln!("Serving HTTP on {}", server.local_addr()?);
+            server.run()?;
+            eprintln!("Server stopped");
+            return Ok(());
+        }
+    };
+
+    if cli.verbose {
+        eprintln!(
+            "Completed in {:?} ({} stale bytes awaiting compaction)",
+            start.elapsed(),
+            store.uncompacted_bytes()
+        );
+    }
+
+    if !found {
+        if !cli.quiet {
+            eprintln!("Key not found");
         }
+        process::exit(EXIT_NOT_FOUND);
     }
 
     Ok(())
 }
+
+/// The value for `set`, from whichever source was given; clap makes sure
+/// there is exactly one.
+fn read_value(value: Option<String>, file: Option<PathBuf>, stdin: bool) -> Result<String> {
+    if let Some(value) = value {
+        return Ok(value);
+    }
+    if let Some(path) = file {
+        return Ok(std::fs::read_to_string(path)?);
+    }
+    debug_assert!(stdin);
+    let mut value = String::new();
+    io::stdin().read_to_string(&mut value)?;
+    Ok(value)
+}
+
+/// Set by the SIGTERM/SIGINT handler; polled by `shutdown_on_signal`.
+#[cfg(all(feature = "http", unix))]
+static SIGNALLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Shuts `server` down cleanly on SIGTERM or SIGINT, so pending writes are
+/// flushed and the log synced before the process exits.
+#[cfg(all(feature = "http", unix))]
+fn shutdown_on_signal(server: Arc<kvstore::server::Server>) {
+    use std::sync::atomic::Ordering;
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        // Only async-signal-safe work here: the watcher thread does the rest.
+        SIGNALLED.store(true, Ordering::SeqCst);
+    }
+
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic.
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+
+    std::thread::spawn(move || {
+        while !SIGNALLED.load(Ordering::SeqCst) {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        eprintln!("Shutting down");
+        if let Err(e) = server.shutdown() {
+            eprintln!("Error: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    });
+}
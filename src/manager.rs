@@ -0,0 +1,180 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, PoisonError, RwLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::engine::KvStore;
+use crate::error::{KvError, Result};
+use crate::metrics::Metrics;
+use crate::options::KvStoreOptions;
+use crate::scheduler;
+
+/// How often the compaction worker checks the stores.
+const COMPACTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+type Stores = RwLock<HashMap<String, Arc<RwLock<KvStore>>>>;
+
+/// Hands out named stores kept in subdirectories of one base directory, so a
+/// service with many logical stores manages them in one place.
+///
+/// Writes to managed stores never compact inline: a single background thread
+/// checks every store and compacts the ones over their threshold, holding a
+/// store's lock only to start and finish, as `CompactionScheduler` does, so a
+/// burst of writes can't stall on a compaction. Stopped on drop.
+pub struct StoreManager {
+    base: PathBuf,
+    options: KvStoreOptions,
+    stores: Arc<Stores>,
+    worker: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StoreManager {
+    /// Manages stores under `base`, opened with default options.
+    pub fn open(base: impl Into<PathBuf>) -> Result<StoreManager> {
+        Self::open_with_options(base, KvStoreOptions::default())
+    }
+
+    /// Manages stores under `base`, each opened with `options`.
+    pub fn open_with_options(base: impl Into<PathBuf>, options: KvStoreOptions) -> Result<StoreManager> {
+        let base = base.into();
+        std::fs::create_dir_all(&base)?;
+
+        let stores: Arc<Stores> = Arc::default();
+        let worker = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let stores = Arc::clone(&stores);
+            let worker = Arc::clone(&worker);
+            thread::spawn(move || run(&stores, &worker))
+        };
+
+        Ok(StoreManager {
+            base,
+            options,
+            stores,
+            worker,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base
+    }
+
+    /// The store called `name`, in the subdirectory of that name, opened on
+    /// first use. Later calls return the same store. Names must be usable as a
+    /// single directory name.
+    pub fn store(&self, name: &str) -> Result<Arc<RwLock<KvStore>>> {
+        validate_name(name)?;
+        if let Some(store) = self.stores.read().unwrap_or_else(PoisonError::into_inner).get(name) {
+            return Ok(Arc::clone(store));
+        }
+
+        let mut stores = self.stores.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(store) = stores.get(name) {
+            return Ok(Arc::clone(store));
+        }
+        let mut store = KvStore::open_with_options(self.base.join(name), self.options.clone())?;
+        store.disable_inline_compaction();
+
+        let store = Arc::new(RwLock::new(store));
+        stores.insert(name.to_string(), Arc::clone(&store));
+        Ok(store)
+    }
+
+    /// Names of the stores opened so far, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.stores.read().unwrap_or_else(PoisonError::into_inner).keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Metrics of every open store, by name.
+    pub fn metrics(&self) -> BTreeMap<String, Metrics> {
+        self.stores
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, store)| (name.clone(), store.read().unwrap_or_else(PoisonError::into_inner).metrics()))
+            .collect()
+    }
+
+    /// Metrics of all open stores added together.
+    pub fn total_metrics(&self) -> Metrics {
+        self.metrics().into_values().sum()
+    }
+
+    /// Runs the background worker's check now: compacts every store that is
+    /// due. Returns the names of the stores compacted.
+    pub fn compact_due(&self) -> Result<Vec<String>> {
+        compact_due(&self.stores)
+    }
+}
+
+impl Drop for StoreManager {
+    fn drop(&mut self) {
+        *self.worker.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.worker.1.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(stores: &Stores, worker: &(Mutex<bool>, Condvar)) {
+    let (stopped, wake) = worker;
+    let mut stopped = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        stopped = wake
+            .wait_timeout(stopped, COMPACTION_CHECK_INTERVAL)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+        if *stopped {
+            return;
+        }
+        if let Err(e) = compact_due(stores) {
+            eprintln!("Warning: background compaction failed: {}", e);
+        }
+    }
+}
+
+/// Compacts each due store in turn, off its write path. A failure
+/// doesn't stop the other stores from being compacted; the first is returned.
+fn compact_due(stores: &Stores) -> Result<Vec<String>> {
+    let mut stores: Vec<(String, Arc<RwLock<KvStore>>)> = stores
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(name, store)| (name.clone(), Arc::clone(store)))
+        .collect();
+    stores.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut compacted = Vec::new();
+    let mut first_error = None;
+    for (name, store) in stores {
+        match scheduler::compact_if_due(&store) {
+            Ok(true) => compacted.push(name),
+            Ok(false) => {}
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(compacted),
+    }
+}
+
+/// A store name becomes a directory name, so it can't be empty, `.`/`..`
+/// or contain a path separator.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(KvError::InvalidStoreName(name.to_string()));
+    }
+    Ok(())
+}
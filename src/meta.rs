@@ -0,0 +1,39 @@
+use std::{
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::create_file;
+use crate::error::Result;
+
+/// Settings persisted next to the log so they survive a reopen.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct StoreMeta {
+    /// Set by `KvStore::set_compaction_threshold`.
+    pub(crate) compaction_threshold: Option<u64>,
+}
+
+/// Writes `meta` to `path` through a temporary file, so a crash leaves
+/// either the old settings or the new ones.
+pub(crate) fn write_meta(path: &Path, meta: &StoreMeta, mode: Option<u32>) -> Result<()> {
+    let tmp_path = path.with_extension("meta.tmp");
+    let mut writer = BufWriter::new(create_file(&tmp_path, mode)?);
+    serde_json::to_writer(&mut writer, meta)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Reads the settings at `path`; `None` if none were ever persisted.
+pub(crate) fn read_meta(path: &Path) -> Result<Option<StoreMeta>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(contents.trim())?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
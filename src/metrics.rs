@@ -0,0 +1,163 @@
+use std::{
+    iter::Sum,
+    ops::Add,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Serialize, Serializer};
+
+/// Cumulative operation counts of a store since it was opened, taken by
+/// `KvStore::metrics`. Counters only grow; sample twice and use `since` for rates.
+///
+/// Serializes to an object of the counts plus `last_compaction_ms`, in
+/// milliseconds since the Unix epoch or `null`; see `KvStore::metrics_json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Metrics {
+    gets: u64,
+    sets: u64,
+    removes: u64,
+    compactions: u64,
+    #[serde(rename = "last_compaction_ms", serialize_with = "unix_millis")]
+    last_compaction: Option<SystemTime>,
+}
+
+impl Metrics {
+    /// Keys looked up, whether found or not; `multi_get` counts each key.
+    pub fn gets(&self) -> u64 {
+        self.gets
+    }
+
+    /// `Set` records written, including those in batches and transactions.
+    pub fn sets(&self) -> u64 {
+        self.sets
+    }
+
+    /// `Remove` records written, including those in transactions.
+    pub fn removes(&self) -> u64 {
+        self.removes
+    }
+
+    /// Compactions since open. Many compactions for few writes means the
+    /// threshold is too low for the workload.
+    pub fn compactions(&self) -> u64 {
+        self.compactions
+    }
+
+    /// When the last compaction finished, by the store's clock; `None` if
+    /// there was none since open.
+    pub fn last_compaction(&self) -> Option<SystemTime> {
+        self.last_compaction
+    }
+
+    /// The operations between an `earlier` sample of the same store and this one.
+    pub fn since(&self, earlier: &Metrics) -> MetricsDelta {
+        MetricsDelta {
+            gets: self.gets.saturating_sub(earlier.gets),
+            sets: self.sets.saturating_sub(earlier.sets),
+            removes: self.removes.saturating_sub(earlier.removes),
+            compactions: self.compactions.saturating_sub(earlier.compactions),
+        }
+    }
+}
+
+/// A time as milliseconds since the Unix epoch, like `store.compacted`.
+fn unix_millis<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    time.map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64)
+        .serialize(serializer)
+}
+
+/// Counts of several stores combined, e.g. by `StoreManager::total_metrics`.
+impl Add for Metrics {
+    type Output = Metrics;
+
+    fn add(self, other: Metrics) -> Metrics {
+        Metrics {
+            gets: self.gets + other.gets,
+            sets: self.sets + other.sets,
+            removes: self.removes + other.removes,
+            compactions: self.compactions + other.compactions,
+            last_compaction: self.last_compaction.max(other.last_compaction),
+        }
+    }
+}
+
+impl Sum for Metrics {
+    fn sum<I: Iterator<Item = Metrics>>(iter: I) -> Metrics {
+        iter.fold(Metrics::default(), Add::add)
+    }
+}
+
+/// Operations between two `Metrics` samples; see `Metrics::since`. Divide by
+/// the time between the samples for per-second rates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsDelta {
+    gets: u64,
+    sets: u64,
+    removes: u64,
+    compactions: u64,
+}
+
+impl MetricsDelta {
+    pub fn gets(&self) -> u64 {
+        self.gets
+    }
+
+    pub fn sets(&self) -> u64 {
+        self.sets
+    }
+
+    pub fn removes(&self) -> u64 {
+        self.removes
+    }
+
+    pub fn compactions(&self) -> u64 {
+        self.compactions
+    }
+}
+
+/// The live counters behind `Metrics`. Atomic so reads through `&self` can count.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    gets: AtomicU64,
+    sets: AtomicU64,
+    removes: AtomicU64,
+    compactions: AtomicU64,
+    last_compaction: Mutex<Option<SystemTime>>,
+}
+
+impl Counters {
+    pub(crate) fn add_gets(&self, n: u64) {
+        self.gets.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_set(&self) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_remove(&self) {
+        self.removes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_compaction(&self, at: SystemTime) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        *self.last_compaction.lock().unwrap_or_else(PoisonError::into_inner) = Some(at);
+    }
+
+    pub(crate) fn last_compaction(&self) -> Option<SystemTime> {
+        *self.last_compaction.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        Metrics {
+            gets: self.gets.load(Ordering::Relaxed),
+            sets: self.sets.load(Ordering::Relaxed),
+            removes: self.removes.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            last_compaction: self.last_compaction(),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::{borrow::Cow, fmt::Debug};
+
+/// Maps keys to the canonical form under which they are stored and looked up,
+/// so keys that normalize alike name the same entry. Set through
+/// `KvStoreOptions::key_normalizer`; applied on every read and write and when
+/// the index is rebuilt from the log.
+///
+/// Changing the normalizer of an existing store is safe: keys already in the
+/// log are normalized as the index is rebuilt, and the last write wins among
+/// keys that now collide.
+pub trait KeyNormalizer: Debug + Send + Sync {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str>;
+}
+
+/// Keys are used exactly as given (the default).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityNormalizer;
+
+impl KeyNormalizer for IdentityNormalizer {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(key)
+    }
+}
+
+/// Keys that differ only by case are equal; stored lowercased.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaseFoldNormalizer;
+
+impl KeyNormalizer for CaseFoldNormalizer {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if key.chars().any(char::is_uppercase) {
+            Cow::Owned(key.to_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
+
+/// Leading and trailing whitespace is ignored. A key that is only whitespace
+/// normalizes to the empty key and is rejected as invalid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrimNormalizer;
+
+impl KeyNormalizer for TrimNormalizer {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(key.trim())
+    }
+}
+
+/// Normalizes an owned key, reusing its buffer when nothing changes.
+pub(crate) fn normalize_owned(normalizer: &dyn KeyNormalizer, key: String) -> String {
+    let normalized = match normalizer.normalize(&key) {
+        Cow::Borrowed(borrowed) if borrowed.len() == key.len() => None,
+        other => Some(other.into_owned()),
+    };
+    normalized.unwrap_or(key)
+}
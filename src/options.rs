@@ -0,0 +1,340 @@
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::cipher::ValueCipher;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{KvError, Result};
+use crate::normalize::{IdentityNormalizer, KeyNormalizer};
+use crate::storage::{OsStorage, Storage};
+use crate::store::Store;
+
+/// Configuration for `KvStore::open_with_options`.
+///
+/// Built with chained setters starting from `KvStoreOptions::default()`;
+/// every setting defaults to the behavior of plain `KvStore::open`.
+#[derive(Debug, Clone)]
+pub struct KvStoreOptions {
+    pub(crate) compaction_threshold: Option<u64>,
+    pub(crate) uncompacted_ceiling: Option<u64>,
+    pub(crate) auto_compaction: bool,
+    pub(crate) bloom_filter: bool,
+    pub(crate) conflict_resolution: ConflictResolution,
+    pub(crate) max_log_age: Option<Duration>,
+    pub(crate) flush_interval: Option<Duration>,
+    pub(crate) skip_unchanged: bool,
+    pub(crate) tolerate_index_desync: bool,
+    pub(crate) tombstone_grace: Option<Duration>,
+    pub(crate) sync_policy: SyncPolicy,
+    pub(crate) sync_method: SyncMethod,
+    pub(crate) hint_format: HintFormat,
+    pub(crate) max_keys: Option<usize>,
+    pub(crate) expected_keys: usize,
+    pub(crate) compaction_temp_dir: Option<PathBuf>,
+    pub(crate) file_mode: Option<u32>,
+    pub(crate) cipher: Option<ValueCipher>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) key_normalizer: Arc<dyn KeyNormalizer>,
+    pub(crate) key_validator: Option<KeyValidator>,
+    pub(crate) fallback: Option<Arc<dyn Store>>,
+}
+
+impl Default for KvStoreOptions {
+    fn default() -> Self {
+        KvStoreOptions {
+            compaction_threshold: None,
+            uncompacted_ceiling: None,
+            auto_compaction: true,
+            bloom_filter: false,
+            conflict_resolution: ConflictResolution::default(),
+            max_log_age: None,
+            flush_interval: None,
+            skip_unchanged: false,
+            tolerate_index_desync: false,
+            tombstone_grace: None,
+            sync_policy: SyncPolicy::default(),
+            sync_method: SyncMethod::default(),
+            hint_format: HintFormat::default(),
+            max_keys: None,
+            expected_keys: 0,
+            compaction_temp_dir: None,
+            file_mode: None,
+            cipher: None,
+            clock: Arc::new(SystemClock),
+            storage: Arc::new(OsStorage),
+            key_normalizer: Arc::new(IdentityNormalizer),
+            key_validator: None,
+            fallback: None,
+        }
+    }
+}
+
+impl KvStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes of stale data that trigger automatic compaction (default: the
+    /// value last persisted by `KvStore::set_compaction_threshold`, else 1MB).
+    /// Overrides the persisted value for this open only.
+    /// See `KvStore::set_compaction_threshold` for how small values behave.
+    pub fn compaction_threshold(mut self, threshold: u64) -> Self {
+        self.compaction_threshold = Some(threshold);
+        self
+    }
+
+    /// A hard limit on reclaimable stale bytes, set well above the compaction
+    /// threshold, for overwrite-heavy loads that outpace compaction. A write
+    /// finding the limit passed first compacts, even if a quarter of the log
+    /// isn't stale yet. Under a `StoreManager`, which compacts in the
+    /// background, it fails with `KvError::Backpressure` instead, so the
+    /// caller can back off and retry while the manager catches up. So it does
+    /// without `auto_compaction`.
+    pub fn uncompacted_ceiling(mut self, ceiling: u64) -> Self {
+        self.uncompacted_ceiling = Some(ceiling);
+        self
+    }
+
+    /// Whether writes compact the log when enough of it is stale (default:
+    /// true). Turned off, the log grows until `KvStore::compact` is called,
+    /// and neither the threshold nor `max_log_age` triggers anything.
+    pub fn auto_compaction(mut self, enabled: bool) -> Self {
+        self.auto_compaction = enabled;
+        self
+    }
+
+    /// Keeps a bloom filter over the live keys so `get` can reject absent
+    /// keys without probing the index.
+    pub fn bloom_filter(mut self, enabled: bool) -> Self {
+        self.bloom_filter = enabled;
+        self
+    }
+
+    /// How `KvStore::apply` settles a write against the value already stored.
+    pub fn conflict_resolution(mut self, policy: ConflictResolution) -> Self {
+        self.conflict_resolution = policy;
+        self
+    }
+
+    /// Caps the number of live keys, making the store a persistent LRU cache:
+    /// a write that would exceed the cap first removes the least recently
+    /// read or written key. Recency isn't logged; after reopening, keys rank
+    /// by the position of their records in the log. At least one key is kept.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys.max(1));
+        self
+    }
+
+    /// Sizes the index for about `keys` live keys before `open` replays the
+    /// log, so a large store's index is allocated once instead of growing
+    /// and rehashing as it fills. Only a hint: more keys still fit, and an
+    /// overestimate just costs memory.
+    pub fn expected_keys(mut self, keys: usize) -> Self {
+        self.expected_keys = keys;
+        self
+    }
+
+    /// Builds the compacted log in `dir` (e.g. a fast local disk) instead of
+    /// the data directory, then moves it into place. Across filesystems the
+    /// move is a copy into the data directory, a sync and a rename, so the
+    /// log is still replaced atomically.
+    pub fn compaction_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.compaction_temp_dir = Some(dir.into());
+        self
+    }
+
+    /// Permission bits for the files the store creates (log, compacted log,
+    /// hint and settings files), e.g. `0o600` to keep values private on a
+    /// shared host. Subject to the umask, like any created file, and only
+    /// applied on creation; the default leaves permissions to the OS.
+    #[cfg(unix)]
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Encrypts every value written from now on with AES-256-GCM under `key`,
+    /// with a fresh nonce per record; keys stay in plaintext so the index can
+    /// be rebuilt without the key. Values already in the log unencrypted
+    /// remain readable. Reading an encrypted value without the key, or with
+    /// a different one, fails with `KvError::Decryption`.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.cipher = Some(ValueCipher::new(&key));
+        self
+    }
+
+    /// Rewrites the log into a fresh file once it is older than `max_age`,
+    /// even if there is little stale data. Checked on every write.
+    pub fn max_log_age(mut self, max_age: Duration) -> Self {
+        self.max_log_age = Some(max_age);
+        self
+    }
+
+    /// Syncs the log to stable storage from a background thread every
+    /// `interval`, so a write is durable within `interval` without paying for
+    /// a sync on every write. The thread stops when the store is dropped.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Makes `set` skip the write when the key already holds the same value,
+    /// so idempotent writers (heartbeats) don't grow the log. Each `set` then
+    /// reads the current value first. See `KvStore::set_if_changed`.
+    pub fn skip_unchanged(mut self, enabled: bool) -> Self {
+        self.skip_unchanged = enabled;
+        self
+    }
+
+    /// Makes reads treat an index entry pointing at anything but a `Set` of
+    /// its key (e.g. a `Remove`) as a missing key, with a warning, instead of
+    /// failing with `KvError::LogCorruption`. Such a desync is a bug, but it
+    /// needn't take a production service down. Unreadable records and
+    /// `KvStore::debug_verify` stay strict.
+    pub fn tolerate_index_desync(mut self, enabled: bool) -> Self {
+        self.tolerate_index_desync = enabled;
+        self
+    }
+
+    /// Keeps `Remove` records through compaction until `grace` has passed
+    /// since the removal, instead of dropping them at the first compaction.
+    /// Gives replicas and merges reading the log time to see the removal.
+    /// Removals found in the log at `open` start their grace period then.
+    pub fn tombstone_grace(mut self, grace: Duration) -> Self {
+        self.tombstone_grace = Some(grace);
+        self
+    }
+
+    /// When writes are forced to stable storage (default: never, beyond
+    /// explicit `KvStore::sync` calls and `flush_interval`).
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Which system call forces the log to stable storage, whenever it is
+    /// synced (`sync_policy`, `flush_interval`, `KvStore::sync`). Default:
+    /// `SyncMethod::Data`.
+    pub fn sync_method(mut self, method: SyncMethod) -> Self {
+        self.sync_method = method;
+        self
+    }
+
+    /// How compaction writes the hint file that `KvStore::quick_stats` reads.
+    /// Default: `HintFormat::Json`. Either format is read back.
+    pub fn hint_format(mut self, format: HintFormat) -> Self {
+        self.hint_format = format;
+        self
+    }
+
+    /// Time source for time-based policies (default: the system clock).
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// File operations behind the log's writes, syncs and renames (default:
+    /// the operating system's).
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.storage = Arc::new(storage);
+        self
+    }
+
+    /// Canonical form applied to every key before it is stored or looked up
+    /// (default: keys are used as given).
+    pub fn key_normalizer(mut self, normalizer: impl KeyNormalizer + 'static) -> Self {
+        self.key_normalizer = Arc::new(normalizer);
+        self
+    }
+
+    /// Extra rules for keys (length, charset, prefix...), checked on every
+    /// write after normalization and after the built-in non-empty check.
+    /// Rejections surface as `KvError::InvalidKey` and nothing is written.
+    pub fn key_validator(mut self, validator: impl Fn(&str) -> Result<()> + Send + Sync + 'static) -> Self {
+        self.key_validator = Some(KeyValidator(Arc::new(validator)));
+        self
+    }
+
+    /// A slower, larger store that `get` consults when a key is absent here,
+    /// e.g. a cold tier behind a small hot one. Nothing is written to it, and
+    /// its values are not copied here unless read with `KvStore::get_and_promote`.
+    pub fn fallback(mut self, store: impl Store + 'static) -> Self {
+        self.fallback = Some(Arc::new(store));
+        self
+    }
+}
+
+type ValidatorFn = dyn Fn(&str) -> Result<()> + Send + Sync;
+
+/// A user-supplied key check, shared between clones of the options.
+#[derive(Clone)]
+pub(crate) struct KeyValidator(Arc<ValidatorFn>);
+
+impl KeyValidator {
+    /// Runs the check; any error it returns is reported as `InvalidKey`.
+    pub(crate) fn check(&self, key: &str) -> Result<()> {
+        match (self.0)(key) {
+            Ok(()) => Ok(()),
+            Err(KvError::InvalidKey(reason)) => Err(KvError::InvalidKey(reason)),
+            Err(e) => Err(KvError::InvalidKey(e.to_string())),
+        }
+    }
+}
+
+impl fmt::Debug for KeyValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyValidator")
+    }
+}
+
+/// Ordering rule for writes merged in through `KvStore::apply`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Whichever write is appended last wins.
+    #[default]
+    LastAppendWins,
+    /// A versioned `Set` is dropped if the stored value carries a higher
+    /// version. Writes where either side is unversioned fall back to
+    /// last-append-wins.
+    HighestVersionWins,
+}
+
+/// How eagerly the store forces data to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Writes are handed to the OS but not synced: they survive the process
+    /// crashing, not the machine.
+    #[default]
+    Never,
+    /// Every write is synced before it returns. Compaction syncs the new log
+    /// before renaming it into place and syncs the directory afterwards, so
+    /// the rename itself survives a power loss.
+    Always,
+}
+
+/// How the log is synced; see `KvStoreOptions::sync_method`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMethod {
+    /// `File::sync_data` (`fdatasync`): the file contents and the metadata
+    /// needed to read them back. Cheaper, since timestamps aren't written.
+    #[default]
+    Data,
+    /// `File::sync_all` (`fsync`): contents and all metadata. For filesystems
+    /// where `fdatasync` doesn't reliably make an appended file's new length
+    /// durable.
+    All,
+}
+
+/// Encoding of the hint file; see `KvStoreOptions::hint_format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HintFormat {
+    /// A JSON line per key. Readable by every version that reads hints.
+    #[default]
+    Json,
+    /// Length-prefixed keys and fixed-width integers behind a versioned
+    /// header. Without field names and decimal digits it is smaller and
+    /// faster to load, more so the shorter the keys. Versions that predate
+    /// it fail to read it.
+    Binary,
+}
+
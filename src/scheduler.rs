@@ -0,0 +1,87 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, PoisonError, RwLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::engine::KvStore;
+use crate::error::Result;
+
+/// Compacts a shared store from a background thread instead of on the write
+/// that crosses the threshold, so writes don't stall behind a compaction.
+///
+/// Every `interval` the thread checks `needs_compaction` and, if due, builds
+/// the compacted log without holding the store's lock. Writers carry on
+/// meanwhile; the lock is only taken to plan the compaction and, at the end,
+/// to copy over what they appended and swap the log. Inline compaction is
+/// turned off, so with `uncompacted_ceiling` writes over the ceiling fail
+/// with `KvError::Backpressure` until the thread catches up. Stopped on drop.
+pub struct CompactionScheduler {
+    store: Arc<RwLock<KvStore>>,
+    worker: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    /// Starts checking `store` every `interval`.
+    pub fn start(store: Arc<RwLock<KvStore>>, interval: Duration) -> CompactionScheduler {
+        store.write().unwrap_or_else(PoisonError::into_inner).disable_inline_compaction();
+
+        let worker = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let store = Arc::clone(&store);
+            let worker = Arc::clone(&worker);
+            thread::spawn(move || run(&store, &worker, interval))
+        };
+        CompactionScheduler { store, worker, thread: Some(thread) }
+    }
+
+    /// Runs the background thread's check now: compacts the store if it is
+    /// due. Returns whether it did.
+    pub fn compact_due(&self) -> Result<bool> {
+        compact_if_due(&self.store)
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        *self.worker.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.worker.1.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(store: &RwLock<KvStore>, worker: &(Mutex<bool>, Condvar), interval: Duration) {
+    let (stopped, wake) = worker;
+    let mut stopped = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        stopped = wake.wait_timeout(stopped, interval).unwrap_or_else(PoisonError::into_inner).0;
+        if *stopped {
+            return;
+        }
+        if let Err(e) = compact_if_due(store) {
+            eprintln!("Warning: background compaction failed: {}", e);
+        }
+    }
+}
+
+/// Compacts `store` if it is due, holding its write lock only to plan the
+/// compaction and to install it. Returns false if it isn't due, or another
+/// thread is already compacting it off the write path.
+pub(crate) fn compact_if_due(store: &RwLock<KvStore>) -> Result<bool> {
+    let plan = {
+        let mut store = store.write().unwrap_or_else(PoisonError::into_inner);
+        if !store.needs_compaction() {
+            return Ok(false);
+        }
+        match store.plan_background_compaction()? {
+            Some(plan) => plan,
+            None => return Ok(false),
+        }
+    };
+    let built = plan.build()?;
+    store.write().unwrap_or_else(PoisonError::into_inner).install_compaction(built)?;
+    Ok(true)
+}
@@ -0,0 +1,466 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, PoisonError, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::engine::KvStore;
+use crate::error::{KvError, Result};
+
+/// Largest request body accepted, bounding memory per connection.
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+/// HTTP/JSON front end for a `KvStore`.
+///
+/// - `GET /kv/:key` responds with the value as the body, or 404
+/// - `PUT /kv/:key` stores the request body as the value
+/// - `DELETE /kv/:key` removes the key, or 404
+/// - `GET /scan?prefix=:prefix` responds with a JSON object of every key
+///   starting with the prefix (all keys if omitted) and its value
+///
+/// Keys are percent-decoded from the path. Errors carry a JSON body
+/// `{"error": "..."}`; log corruption is a 500 that also reports the `offset`.
+/// Every connection gets its own thread and is kept alive per HTTP/1.1 rules.
+pub struct Server {
+    listener: TcpListener,
+    store: Arc<RwLock<KvStore>>,
+    write_window: Duration,
+    stopping: AtomicBool,
+}
+
+/// State shared by every connection thread.
+#[derive(Clone)]
+struct Shared {
+    store: Arc<RwLock<KvStore>>,
+    /// Queue to the write coalescer, when a write window is configured.
+    writes: Option<Sender<PendingSet>>,
+}
+
+/// A `PUT` waiting for the coalescer to write its batch.
+struct PendingSet {
+    key: String,
+    val: String,
+    reply: SyncSender<std::result::Result<(), String>>,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, store: KvStore) -> Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            store: Arc::new(RwLock::new(store)),
+            write_window: Duration::ZERO,
+            stopping: AtomicBool::new(false),
+        })
+    }
+
+    /// Collects `PUT`s arriving within `window` of each other into one
+    /// `set_many` batch with a single flush (group commit at the request
+    /// layer). Each request still waits for its batch before responding, so
+    /// this trades latency for fewer flushes and only pays off when flushing
+    /// is expensive. Zero, the default, writes every request on its own.
+    pub fn set_write_window(&mut self, window: Duration) {
+        self.write_window = window;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Asks a running `run` to stop: it stops accepting connections, lets
+    /// requests already being served finish, writes any pending coalesced
+    /// writes, syncs the log and returns. Callable from any thread.
+    pub fn shutdown(&self) -> Result<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        // `accept` only notices the flag once it returns; give it a connection.
+        match TcpStream::connect(self.local_addr()?) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Accepts and serves connections until `shutdown` is called.
+    pub fn run(&self) -> Result<()> {
+        let (writes, coalescer) = if self.write_window.is_zero() {
+            (None, None)
+        } else {
+            let (tx, rx) = mpsc::channel();
+            let store = Arc::clone(&self.store);
+            let window = self.write_window;
+            let handle = thread::spawn(move || coalesce_writes(&store, rx, window));
+            (Some(tx), Some(handle))
+        };
+        let shared = Shared {
+            store: Arc::clone(&self.store),
+            writes,
+        };
+
+        // Open connections, so shutdown can end them and wait for their requests.
+        let mut connections: Vec<(TcpStream, JoinHandle<()>)> = Vec::new();
+        for stream in self.listener.incoming() {
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Warning: failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            connections.retain(|(_, handle)| !handle.is_finished());
+            let shared = shared.clone();
+            let handle = match stream.try_clone() {
+                Ok(clone) => thread::spawn(move || {
+                    if let Err(e) = handle_connection(clone, &shared) {
+                        eprintln!("Warning: connection error: {}", e);
+                    }
+                }),
+                Err(e) => {
+                    eprintln!("Warning: failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            connections.push((stream, handle));
+        }
+
+        // Closing the read side lets a connection finish the request it is
+        // serving, then see end of stream instead of waiting for another.
+        for (stream, handle) in connections {
+            let _ = stream.shutdown(Shutdown::Read);
+            let _ = handle.join();
+        }
+        // With every sender gone, the coalescer writes what is queued and exits.
+        drop(shared);
+        if let Some(coalescer) = coalescer {
+            let _ = coalescer.join();
+        }
+
+        self.store.write().unwrap_or_else(PoisonError::into_inner).sync()
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn empty(status: u16) -> Response {
+        Response { status, content_type: "text/plain", body: Vec::new() }
+    }
+
+    fn text(status: u16, body: String) -> Response {
+        Response { status, content_type: "text/plain; charset=utf-8", body: body.into_bytes() }
+    }
+
+    fn json(status: u16, body: serde_json::Value) -> Response {
+        Response { status, content_type: "application/json", body: body.to_string().into_bytes() }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, json!({ "error": message }))
+    }
+
+    fn from_kv_error(e: &KvError) -> Response {
+        let message = e.to_string();
+        match e {
+            KvError::KeyNotFound => Response::error(404, &message),
+            KvError::InvalidKey(_) => Response::error(400, &message),
+            KvError::LogCorruption(offset) => {
+                Response::json(500, json!({ "error": message, "offset": offset }))
+            }
+            _ => Response::error(500, &message),
+        }
+    }
+}
+
+/// Writes queued `PUT`s in batches: everything that arrives within `window`
+/// of the first pending write goes out in one `set_many`.
+fn coalesce_writes(store: &RwLock<KvStore>, rx: Receiver<PendingSet>, window: Duration) {
+    while let Ok(first) = rx.recv() {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(remaining) {
+                Ok(pending) => batch.push(pending),
+                Err(_) => break,
+            }
+        }
+
+        let (pairs, replies): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| ((pending.key, pending.val), pending.reply))
+            .unzip();
+
+        let result = store
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_many(pairs)
+            .map_err(|e| e.to_string());
+
+        for reply in replies {
+            let _ = reply.send(result.clone());
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let result = serve_requests(&stream, shared);
+    // `run` holds a clone of the stream for shutdown, so dropping ours
+    // wouldn't close the connection.
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn serve_requests(stream: &TcpStream, shared: &Shared) -> io::Result<()> {
+    // Responses are small and latency-bound; don't let Nagle hold them back.
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream);
+
+    while let Some(request) = read_request(&mut reader)? {
+        let response = route(&request, shared);
+        write_response(&mut writer, &response, request.keep_alive)?;
+        if !request.keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads one request, or `None` if the client closed the connection.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let message = match read_message(reader)? {
+        Some(message) => message,
+        None => return Ok(None),
+    };
+
+    let mut parts = message.start_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid_data("malformed request line")),
+    };
+
+    Ok(Some(Request {
+        method,
+        path,
+        body: message.body,
+        keep_alive: message.keep_alive,
+    }))
+}
+
+/// One HTTP/1.x request or response, as far as this crate needs to read it.
+pub(crate) struct Message {
+    pub(crate) start_line: String,
+    pub(crate) body: Vec<u8>,
+    pub(crate) keep_alive: bool,
+}
+
+/// Reads a message framed by `Content-Length`, or `None` at a clean end of stream.
+pub(crate) fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut start_line = String::new();
+    if reader.read_line(&mut start_line)? == 0 {
+        return Ok(None);
+    }
+    let start_line = start_line.trim_end().to_string();
+
+    let mut keep_alive = start_line.split_whitespace().any(|part| part == "HTTP/1.1");
+    let mut content_length = 0usize;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid_data("connection closed inside headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().map_err(|_| invalid_data("bad content-length"))?;
+            } else if name.eq_ignore_ascii_case("connection") {
+                keep_alive = !value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid_data("message body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Message { start_line, body, keep_alive }))
+}
+
+fn route(request: &Request, shared: &Shared) -> Response {
+    let store = &shared.store;
+    if let Some(query) = request.path.strip_prefix("/scan") {
+        return scan(request, query, store);
+    }
+    let key = match request.path.strip_prefix("/kv/").map(percent_decode) {
+        Some(Some(key)) => key,
+        Some(None) => return Response::error(400, "Malformed key encoding"),
+        None => return Response::error(404, "Not found"),
+    };
+
+    let result = match request.method.as_str() {
+        "GET" => {
+            let store = store.read().unwrap_or_else(PoisonError::into_inner);
+            store.get(&key).map(|value| match value {
+                Some(value) => Response::text(200, value),
+                None => Response::from_kv_error(&KvError::KeyNotFound),
+            })
+        }
+        "PUT" => {
+            let value = match String::from_utf8(request.body.clone()) {
+                Ok(value) => value,
+                Err(_) => return Response::error(400, "Value must be valid UTF-8"),
+            };
+            match &shared.writes {
+                Some(writes) => return coalesced_set(writes, store, key, value),
+                None => {
+                    let mut store = store.write().unwrap_or_else(PoisonError::into_inner);
+                    store.set(key, value).map(|()| Response::empty(204))
+                }
+            }
+        }
+        "DELETE" => {
+            let mut store = store.write().unwrap_or_else(PoisonError::into_inner);
+            store.remove(key).map(|()| Response::empty(204))
+        }
+        _ => return Response::error(405, "Method not allowed"),
+    };
+
+    result.unwrap_or_else(|e| Response::from_kv_error(&e))
+}
+
+/// Serves `GET /scan`. The scan reads from a snapshot, so it holds the store
+/// lock only to take the snapshot and sees one consistent state even while
+/// other connections write and compact.
+fn scan(request: &Request, query: &str, store: &RwLock<KvStore>) -> Response {
+    if request.method != "GET" {
+        return Response::error(405, "Method not allowed");
+    }
+    let prefix = match query {
+        "" => String::new(),
+        query => match query.strip_prefix("?prefix=").map(percent_decode) {
+            Some(Some(prefix)) => prefix,
+            Some(None) => return Response::error(400, "Malformed prefix encoding"),
+            None => return Response::error(404, "Not found"),
+        },
+    };
+
+    let snapshot = store.read().unwrap_or_else(PoisonError::into_inner).snapshot();
+    let entries = snapshot.and_then(|snapshot| snapshot.scan(&prefix));
+    match entries {
+        Ok(entries) => {
+            let map: serde_json::Map<_, _> = entries
+                .into_iter()
+                .map(|(key, val)| (key, serde_json::Value::String(val)))
+                .collect();
+            Response::json(200, serde_json::Value::Object(map))
+        }
+        Err(e) => Response::from_kv_error(&e),
+    }
+}
+
+/// Hands a `PUT` to the coalescer and waits for its batch to be written.
+fn coalesced_set(writes: &Sender<PendingSet>, store: &RwLock<KvStore>, key: String, val: String) -> Response {
+    // Reject bad keys up front so one request can't fail a whole batch.
+    let checked = store.read().unwrap_or_else(PoisonError::into_inner).check_key(&key);
+    if let Err(e) = checked {
+        return Response::from_kv_error(&e);
+    }
+
+    let (reply, done) = mpsc::sync_channel(1);
+    if writes.send(PendingSet { key, val, reply }).is_err() {
+        return Response::error(500, "Write coalescer stopped");
+    }
+
+    match done.recv() {
+        Ok(Ok(())) => Response::empty(204),
+        Ok(Err(message)) => Response::error(500, &message),
+        Err(_) => Response::error(500, "Write coalescer stopped"),
+    }
+}
+
+fn write_response(writer: &mut impl Write, response: &Response, keep_alive: bool) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+/// Escapes everything but RFC 3986 unreserved characters, for keys in paths.
+pub(crate) fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decodes `%XX` escapes; `None` if an escape is malformed or the result isn't UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+use crate::cipher::{self, ValueCipher};
+use crate::cmd::Command;
+use crate::engine::LogPointer;
+use crate::error::{KvError, Result};
+use crate::normalize::KeyNormalizer;
+
+/// A read-only, point-in-time view of a store, created by `KvStore::snapshot`.
+///
+/// Holds a copy of the index and an open handle on the log as it was when the
+/// snapshot was taken. Later writes only append past the records it points at,
+/// and compaction renames a new log into place while this handle keeps the old
+/// one readable, so reads see the same data for as long as the snapshot lives.
+pub struct Snapshot {
+    index: HashMap<String, LogPointer>,
+    log: Mutex<BufReader<File>>,
+    normalizer: Arc<dyn KeyNormalizer>,
+    cipher: Option<ValueCipher>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(
+        index: HashMap<String, LogPointer>,
+        log: File,
+        normalizer: Arc<dyn KeyNormalizer>,
+        cipher: Option<ValueCipher>,
+    ) -> Snapshot {
+        Snapshot {
+            index,
+            log: Mutex::new(BufReader::new(log)),
+            normalizer,
+            cipher,
+        }
+    }
+
+    /// Number of live keys in the snapshot.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let key = self.normalizer.normalize(key);
+        match self.index.get(&*key) {
+            Some(ptr) => self.read_value(&key, ptr).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Every key starting with `prefix` and its value, sorted by key. Keys are
+    /// in normalized form; the prefix is matched as given.
+    pub fn scan(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut keys: Vec<&String> = self.index.keys().filter(|key| key.starts_with(prefix)).collect();
+        keys.sort();
+
+        keys.into_iter()
+            .map(|key| Ok((key.clone(), self.read_value(key, &self.index[key])?)))
+            .collect()
+    }
+
+    /// Reads the value of `key` at `ptr`; a record for any other key there is corruption.
+    fn read_value(&self, key: &str, ptr: &LogPointer) -> Result<String> {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.seek(SeekFrom::Start(ptr.offset()))?;
+
+        let mut line = String::new();
+        log.read_line(&mut line)?;
+
+        match serde_json::from_str(line.trim()) {
+            Ok(Command::Set { key: stored, val, nonce, .. }) if self.normalizer.normalize(&stored) == key => {
+                cipher::open_value(self.cipher.as_ref(), val, nonce)
+            }
+            _ => Err(KvError::LogCorruption(ptr.offset())),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::options::SyncMethod;
+
+/// The file operations the store's durability guarantees rest on: opening
+/// and appending to the log, syncing it, syncing its directory and renaming
+/// a compacted log over it. Swappable through `KvStoreOptions::storage` so tests can make them
+/// fail or record that they happened. Every method defaults to the real
+/// operation, so an implementation only overrides what it intercepts.
+pub trait Storage: Debug + Send + Sync {
+    /// Opens the log at `path` for appending, as `OpenOptions::open` does.
+    fn open_log(&self, path: &Path, options: &OpenOptions) -> io::Result<File> {
+        options.open(path)
+    }
+
+    /// Writes some of `buf` to the end of the log, or of a compacted copy
+    /// being built, as `Write::write` does.
+    fn write(&self, log: &mut File, buf: &[u8]) -> io::Result<usize> {
+        log.write(buf)
+    }
+
+    /// Syncs `file` to stable storage with `method`.
+    fn sync(&self, file: &File, method: SyncMethod) -> io::Result<()> {
+        match method {
+            SyncMethod::Data => file.sync_data(),
+            SyncMethod::All => file.sync_all(),
+        }
+    }
+
+    /// Syncs the directory at `path`, making renames and file creations in
+    /// it durable. Only meaningful on unix; elsewhere directories can't be
+    /// opened for syncing.
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(path)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    /// Renames `from` to `to`, as `std::fs::rename` does.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+}
+
+/// The operating system's files (the default).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsStorage;
+
+impl Storage for OsStorage {}
+
+/// The open log, writing through a `Storage`.
+pub(crate) struct LogFile {
+    pub(crate) file: File,
+    storage: Arc<dyn Storage>,
+}
+
+impl LogFile {
+    pub(crate) fn new(file: File, storage: Arc<dyn Storage>) -> LogFile {
+        LogFile { file, storage }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.storage.write(&mut self.file, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for LogFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
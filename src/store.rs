@@ -0,0 +1,61 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use crate::engine::KvStore;
+use crate::error::{KvError, Result};
+
+/// The basic key-value operations, so code can be written against any
+/// backend and stores can be layered: a `KvStore` can fall back to any
+/// `Store` through `KvStoreOptions::fallback`.
+///
+/// Implemented for `KvStore` and, as a simple in-memory backend for tests,
+/// for `HashMap<String, String>`. Like `KvStore::remove`, `remove` of an
+/// absent key is `KvError::KeyNotFound`.
+pub trait Store: Debug + Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    fn set(&mut self, key: String, val: String) -> Result<()>;
+
+    fn remove(&mut self, key: String) -> Result<()>;
+
+    fn contains_key(&self, key: &str) -> Result<bool>;
+}
+
+impl Store for KvStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        KvStore::get(self, key)
+    }
+
+    fn set(&mut self, key: String, val: String) -> Result<()> {
+        KvStore::set(self, key, val)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        KvStore::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool> {
+        KvStore::contains_key(self, key)
+    }
+}
+
+impl Store for HashMap<String, String> {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(HashMap::get(self, key).cloned())
+    }
+
+    fn set(&mut self, key: String, val: String) -> Result<()> {
+        self.insert(key, val);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        match HashMap::remove(self, &key) {
+            Some(_) => Ok(()),
+            None => Err(KvError::KeyNotFound),
+        }
+    }
+
+    fn contains_key(&self, key: &str) -> Result<bool> {
+        Ok(HashMap::contains_key(self, key))
+    }
+}
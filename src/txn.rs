@@ -0,0 +1,34 @@
+use crate::cmd::Command;
+use crate::engine::KvStore;
+use crate::error::Result;
+
+/// A group of writes applied atomically, created by `KvStore::transaction`.
+///
+/// Operations are buffered in memory until `commit`, which appends them between
+/// `Begin`/`Commit` markers in one flush. After a crash, reopening the store
+/// shows either every operation of the transaction or none of them.
+/// Dropping a `Txn` without committing discards it.
+pub struct Txn<'a> {
+    store: &'a mut KvStore,
+    ops: Vec<Command>,
+}
+
+impl<'a> Txn<'a> {
+    pub(crate) fn new(store: &'a mut KvStore) -> Txn<'a> {
+        Txn { store, ops: Vec::new() }
+    }
+
+    pub fn set(&mut self, key: String, val: String) {
+        self.ops.push(Command::Set { key, val, version: None, nonce: None, written_at: None, schema: None });
+    }
+
+    pub fn remove(&mut self, key: String) {
+        self.ops.push(Command::Remove { key });
+    }
+
+    /// Writes all buffered operations. Fails without writing anything if a key
+    /// is invalid or a `remove` targets a key that is absent at that point.
+    pub fn commit(self) -> Result<()> {
+        self.store.commit_batch(self.ops)
+    }
+}
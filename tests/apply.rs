@@ -0,0 +1,55 @@
+use kvstore::cmd::Command;
+use kvstore::{ConflictResolution, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+fn set(key: &str, val: &str, version: Option<u64>) -> Command {
+    Command::Set { key: key.to_string(), val: val.to_string(), version, nonce: None, written_at: None, schema: None }
+}
+
+fn highest_version_wins() -> KvStoreOptions {
+    KvStoreOptions::new().conflict_resolution(ConflictResolution::HighestVersionWins)
+}
+
+#[test]
+fn older_version_does_not_clobber_newer() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), highest_version_wins()).unwrap();
+
+    assert!(store.apply(set("a", "new", Some(2))).unwrap());
+    assert!(!store.apply(set("a", "old", Some(1))).unwrap());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("new"));
+
+    // The stored version is read back from the log after a reopen.
+    drop(store);
+    let mut store = KvStore::open_with_options(dir.path(), highest_version_wins()).unwrap();
+    assert!(!store.apply(set("a", "old", Some(1))).unwrap());
+    assert!(store.apply(set("a", "same", Some(2))).unwrap());
+    assert!(store.apply(set("a", "newer", Some(3))).unwrap());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("newer"));
+}
+
+#[test]
+fn unversioned_writes_fall_back_to_last_append() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), highest_version_wins()).unwrap();
+
+    assert!(store.apply(set("a", "versioned", Some(5))).unwrap());
+    assert!(store.apply(set("a", "plain", None)).unwrap());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("plain"));
+    assert!(store.apply(set("a", "low", Some(1))).unwrap());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("low"));
+}
+
+#[test]
+fn last_append_wins_by_default() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+
+    assert!(store.apply(set("a", "new", Some(2))).unwrap());
+    assert!(store.apply(set("a", "old", Some(1))).unwrap());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("old"));
+
+    assert!(store.apply(Command::Remove { key: "a".to_string() }).unwrap());
+    assert!(!store.apply(Command::Remove { key: "a".to_string() }).unwrap());
+    assert!(!store.apply(Command::Begin).unwrap());
+}
@@ -0,0 +1,43 @@
+use kvstore::{AsyncKvStore, KvStore};
+use tempfile::TempDir;
+
+const TASKS: usize = 8;
+const KEYS_PER_TASK: usize = 50;
+
+#[test]
+fn concurrent_gets_and_sets() {
+    let dir = TempDir::new().unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+    runtime.block_on(async {
+        let store = AsyncKvStore::open(dir.path()).await.unwrap();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for i in 0..KEYS_PER_TASK {
+                        let key = format!("key{}-{}", task, i);
+                        store.set(key.clone(), i.to_string()).await.unwrap();
+                        assert_eq!(store.get(key).await.unwrap(), Some(i.to_string()));
+                        // Read another task's keys while it writes them.
+                        let other = format!("key{}-{}", (task + 1) % TASKS, i);
+                        if let Some(val) = store.get(other).await.unwrap() {
+                            assert_eq!(val, i.to_string());
+                        }
+                    }
+                    store.remove(format!("key{}-0", task)).await.unwrap();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(store.get("key0-0".to_string()).await.unwrap(), None);
+        assert_eq!(store.get("key0-1".to_string()).await.unwrap().as_deref(), Some("1"));
+    });
+    drop(runtime);
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.len().unwrap(), TASKS * (KEYS_PER_TASK - 1));
+    store.debug_verify().unwrap();
+}
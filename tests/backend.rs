@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use kvstore::{KvError, KvStore, Store};
+use tempfile::TempDir;
+
+/// Backend-agnostic use of a store: a counter kept as a string.
+fn bump(store: &mut impl Store, key: &str) -> kvstore::Result<u64> {
+    let count = match store.get(key)? {
+        Some(val) => val.parse::<u64>().unwrap() + 1,
+        None => 1,
+    };
+    store.set(key.to_string(), count.to_string())?;
+    Ok(count)
+}
+
+fn exercise(store: &mut impl Store) {
+    assert_eq!(bump(store, "hits").unwrap(), 1);
+    assert_eq!(bump(store, "hits").unwrap(), 2);
+    assert!(store.contains_key("hits").unwrap());
+    assert_eq!(store.get("hits").unwrap().as_deref(), Some("2"));
+
+    store.remove("hits".to_string()).unwrap();
+    assert!(!store.contains_key("hits").unwrap());
+    assert!(matches!(store.remove("hits".to_string()), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get("hits").unwrap(), None);
+}
+
+#[test]
+fn generic_code_runs_on_kv_store() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    exercise(&mut store);
+}
+
+#[test]
+fn generic_code_runs_in_memory() {
+    let mut store = HashMap::new();
+    exercise(&mut store);
+}
+
+#[test]
+fn stores_work_as_trait_objects() {
+    let dir = TempDir::new().unwrap();
+    let mut stores: Vec<Box<dyn Store>> = vec![Box::new(KvStore::open(dir.path()).unwrap()), Box::new(HashMap::new())];
+    for store in &mut stores {
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    }
+}
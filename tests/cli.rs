@@ -0,0 +1,190 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+/// Runs the `kvstore` binary on the store in `dir`.
+fn kvstore(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kvstore")).arg("--data-dir").arg(dir).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn exit_codes_distinguish_misses_from_errors() {
+    let dir = TempDir::new().unwrap();
+
+    let set = kvstore(dir.path(), &["set", "a", "1"]);
+    assert_eq!(set.status.code(), Some(0));
+    let get = kvstore(dir.path(), &["get", "a"]);
+    assert_eq!((get.status.code(), stdout(&get)), (Some(0), "1\n"));
+
+    let miss = kvstore(dir.path(), &["get", "missing"]);
+    assert_eq!(miss.status.code(), Some(2));
+    assert_eq!((stdout(&miss), stderr(&miss)), ("", "Key not found\n"));
+    let rm = kvstore(dir.path(), &["rm", "missing"]);
+    assert_eq!(rm.status.code(), Some(2));
+    assert_eq!(stderr(&rm), "Error: Key not found\n");
+
+    // A data dir that is a file can't be opened at all.
+    let file = dir.path().join("store.log");
+    let broken = kvstore(&file, &["get", "a"]);
+    assert_eq!(broken.status.code(), Some(1));
+    assert!(stderr(&broken).starts_with("Error: "), "{}", stderr(&broken));
+}
+
+#[test]
+fn quiet_leaves_misses_to_the_exit_code() {
+    let dir = TempDir::new().unwrap();
+    let miss = kvstore(dir.path(), &["--quiet", "get", "missing"]);
+    assert_eq!(miss.status.code(), Some(2));
+    assert_eq!((stdout(&miss), stderr(&miss)), ("", ""));
+
+    let rm = kvstore(dir.path(), &["rm", "missing", "-q"]);
+    assert_eq!((rm.status.code(), stderr(&rm)), (Some(2), ""));
+
+    // Values are still printed.
+    kvstore(dir.path(), &["set", "a", "1"]);
+    let get = kvstore(dir.path(), &["-q", "get", "a"]);
+    assert_eq!((stdout(&get), stderr(&get)), ("1\n", ""));
+}
+
+#[test]
+fn verbose_reports_timings_on_stderr() {
+    let dir = TempDir::new().unwrap();
+    let quiet_by_default = kvstore(dir.path(), &["set", "a", "1"]);
+    assert_eq!(stderr(&quiet_by_default), "");
+
+    let set = kvstore(dir.path(), &["--verbose", "set", "a", "2"]);
+    assert_eq!(set.status.code(), Some(0));
+    let lines: Vec<&str> = stderr(&set).lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].starts_with(&format!("Opened {} in ", dir.path().display())), "{}", lines[0]);
+    assert!(lines[1].starts_with("Completed in "), "{}", lines[1]);
+    assert!(lines[1].ends_with(" stale bytes awaiting compaction)"), "{}", lines[1]);
+
+    let get = kvstore(dir.path(), &["-v", "get", "a"]);
+    assert_eq!(stdout(&get), "2\n");
+    assert!(stderr(&get).contains("Completed in "));
+
+    let both = kvstore(dir.path(), &["-q", "-v", "get", "a"]);
+    assert!(!both.status.success());
+    assert!(stderr(&both).contains("cannot be used with"), "{}", stderr(&both));
+}
+
+#[test]
+fn compact_dry_run_leaves_the_log_alone() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..5 {
+        kvstore(dir.path(), &["set", "a", &i.to_string()]);
+    }
+    let log = std::fs::read(dir.path().join("store.log")).unwrap();
+
+    let dry_run = kvstore(dir.path(), &["compact", "--dry-run"]);
+    assert_eq!(dry_run.status.code(), Some(0));
+    assert!(stdout(&dry_run).starts_with(&format!("Would compact {} -> ", log.len())), "{}", stdout(&dry_run));
+    assert_eq!(std::fs::read(dir.path().join("store.log")).unwrap(), log);
+
+    let compact = kvstore(dir.path(), &["compact"]);
+    assert!(stdout(&compact).starts_with(&format!("Compacted {} -> ", log.len())), "{}", stdout(&compact));
+    assert!(std::fs::read(dir.path().join("store.log")).unwrap().len() < log.len());
+}
+
+#[test]
+fn empty_value_prints_a_bare_newline() {
+    let dir = TempDir::new().unwrap();
+    assert_eq!(kvstore(dir.path(), &["set", "empty", ""]).status.code(), Some(0));
+
+    let get = kvstore(dir.path(), &["get", "empty"]);
+    assert_eq!((get.status.code(), stdout(&get), stderr(&get)), (Some(0), "\n", ""));
+    let miss = kvstore(dir.path(), &["-q", "get", "missing"]);
+    assert_eq!((miss.status.code(), stdout(&miss)), (Some(2), ""));
+}
+
+#[test]
+fn set_reads_the_value_from_a_file_or_stdin() {
+    let dir = TempDir::new().unwrap();
+    let value = format!("{}\nsecond line with 'quotes' and \"more\"\n", "x".repeat(8192));
+    let file = dir.path().join("value.txt");
+    std::fs::write(&file, &value).unwrap();
+
+    let set = kvstore(dir.path(), &["set", "big", "--value-file", file.to_str().unwrap()]);
+    assert_eq!(set.status.code(), Some(0), "{}", stderr(&set));
+    let get = kvstore(dir.path(), &["get", "big"]);
+    assert_eq!(stdout(&get), format!("{}\n", value));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kvstore"))
+        .arg("--data-dir")
+        .arg(dir.path())
+        .args(["set", "piped", "--value-stdin"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"from stdin").unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(stdout(&kvstore(dir.path(), &["get", "piped"])), "from stdin\n");
+
+    // Exactly one source is required.
+    assert!(!kvstore(dir.path(), &["set", "a"]).status.success());
+    assert!(!kvstore(dir.path(), &["set", "a", "1", "--value-file", file.to_str().unwrap()]).status.success());
+    let missing = kvstore(dir.path(), &["set", "a", "--value-file", "/nonexistent/value"]);
+    assert_eq!(missing.status.code(), Some(1));
+}
+
+#[test]
+fn get_default_stands_in_for_a_missing_key() {
+    let dir = TempDir::new().unwrap();
+    kvstore(dir.path(), &["set", "a", "1"]);
+
+    let present = kvstore(dir.path(), &["get", "a", "--default", "fallback"]);
+    assert_eq!((present.status.code(), stdout(&present)), (Some(0), "1\n"));
+    let absent = kvstore(dir.path(), &["get", "missing", "--default", "fallback"]);
+    assert_eq!((absent.status.code(), stdout(&absent), stderr(&absent)), (Some(0), "fallback\n", ""));
+    let empty = kvstore(dir.path(), &["get", "missing", "--default", ""]);
+    assert_eq!((empty.status.code(), stdout(&empty)), (Some(0), "\n"));
+}
+
+#[test]
+fn no_auto_create_refuses_a_missing_store() {
+    let dir = TempDir::new().unwrap();
+    let typo = dir.path().join("typo");
+    let missing = kvstore(&typo, &["--no-auto-create", "get", "a"]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(stderr(&missing).starts_with("Error: "), "{}", stderr(&missing));
+    assert!(!typo.exists());
+
+    kvstore(dir.path(), &["set", "a", "1"]);
+    let existing = kvstore(dir.path(), &["get", "a", "--no-auto-create"]);
+    assert_eq!((existing.status.code(), stdout(&existing)), (Some(0), "1\n"));
+}
+
+#[test]
+fn stats_prints_json_on_request() {
+    let dir = TempDir::new().unwrap();
+    kvstore(dir.path(), &["set", "a", "1"]);
+    kvstore(dir.path(), &["set", "a", "2"]);
+    kvstore(dir.path(), &["set", "b", "3"]);
+
+    let stats = kvstore(dir.path(), &["stats", "--output", "json"]);
+    assert_eq!(stats.status.code(), Some(0));
+    let json: serde_json::Value = serde_json::from_str(stdout(&stats)).unwrap();
+    let log_bytes = std::fs::metadata(dir.path().join("store.log")).unwrap().len();
+    assert_eq!(json["keys"], 2);
+    assert_eq!(json["log_bytes"], log_bytes);
+    assert!(json["stale_bytes"].as_u64().unwrap() > 0);
+    // A fresh process has counted nothing yet.
+    assert_eq!(
+        json["metrics"],
+        serde_json::json!({"gets": 0, "sets": 0, "removes": 0, "compactions": 0, "last_compaction_ms": null})
+    );
+
+    let text = kvstore(dir.path(), &["stats"]);
+    assert!(stdout(&text).starts_with("keys: 2\n"), "{}", stdout(&text));
+}
@@ -0,0 +1,66 @@
+#![allow(dead_code)]
+
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use kvstore::cmd::Command;
+use kvstore::engine::read_log;
+use kvstore::{OsStorage, Storage};
+
+/// The records of the log in `dir`, as `"Set a=1"`, `"Remove a"`, `"Begin"`
+/// or `"Commit 2"`, so tests can compare whole logs at a glance.
+pub fn log_records(dir: &Path) -> Vec<String> {
+    read_log(&dir.join("store.log"))
+        .unwrap()
+        .map(|record| match record.unwrap().1 {
+            Command::Set { key, val, .. } => format!("Set {}={}", key, val),
+            Command::Remove { key } => format!("Remove {}", key),
+            Command::Begin => "Begin".to_string(),
+            Command::Commit { count } => format!("Commit {}", count),
+        })
+        .collect()
+}
+
+/// The raw records of the log in `dir`, each with its newline, for tests
+/// that damage or cut the log.
+pub fn log_lines(dir: &Path) -> Vec<String> {
+    std::fs::read_to_string(dir.join("store.log")).unwrap().split_inclusive('\n').map(str::to_string).collect()
+}
+
+/// Lets `budget` more bytes through to the log and compacted copies, then
+/// either fails every write or, with `silent` set, claims to write what it
+/// drops.
+#[derive(Debug, Clone, Default)]
+pub struct FailAfter {
+    pub budget: Arc<Mutex<Option<usize>>>,
+    pub silent: bool,
+}
+
+impl FailAfter {
+    pub fn limit(&self, bytes: usize) {
+        *self.budget.lock().unwrap() = Some(bytes);
+    }
+
+    pub fn unlimit(&self) {
+        *self.budget.lock().unwrap() = None;
+    }
+}
+
+impl Storage for FailAfter {
+    fn write(&self, log: &mut File, buf: &[u8]) -> io::Result<usize> {
+        let mut budget = self.budget.lock().unwrap();
+        let Some(left) = budget.as_mut() else {
+            return OsStorage.write(log, buf);
+        };
+        if *left == 0 {
+            return if self.silent { Ok(buf.len()) } else { Err(io::Error::other("disk full")) };
+        }
+        let written = OsStorage.write(log, &buf[..buf.len().min(*left)])?;
+        *left -= written;
+        Ok(if self.silent { buf.len() } else { written })
+    }
+}
@@ -0,0 +1,390 @@
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{log_lines, log_records};
+use kvstore::{KvError, KvStore, KvStoreOptions, MockClock};
+use tempfile::TempDir;
+
+#[test]
+fn compacted_log_is_in_key_order() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["m", "c", "x", "a", "q", "c", "b"] {
+        store.set(key.to_string(), key.to_uppercase()).unwrap();
+    }
+    store.remove("q".to_string()).unwrap();
+
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set a=A", "Set b=B", "Set c=C", "Set m=M", "Set x=X"]);
+
+    // Writes after compaction land in arrival order until the next one.
+    store.set("d".to_string(), "D".to_string()).unwrap();
+    assert_eq!(log_records(dir.path()).last().unwrap(), "Set d=D");
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set a=A", "Set b=B", "Set c=C", "Set d=D", "Set m=M", "Set x=X"]);
+    drop(store);
+    KvStore::open(dir.path()).unwrap().debug_verify().unwrap();
+}
+
+#[test]
+fn log_older_than_max_age_is_rotated() {
+    let dir = TempDir::new().unwrap();
+    let clock = MockClock::new(SystemTime::now());
+    let options = KvStoreOptions::new().clock(clock.clone()).max_log_age(Duration::from_secs(3600));
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+
+    clock.advance(Duration::from_secs(1800));
+    store.set("b".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 0);
+    assert!(!store.needs_compaction());
+
+    clock.advance(Duration::from_secs(1800));
+    assert!(store.needs_compaction());
+    store.set("c".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 1);
+    assert_eq!(log_records(dir.path()), ["Set a=2", "Set b=1", "Set c=1"]);
+
+    // The rotated log starts a new age.
+    clock.advance(Duration::from_secs(1800));
+    store.set("d".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 1);
+}
+
+#[test]
+fn zero_threshold_compacts_without_looping() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set_compaction_threshold(0);
+    assert_eq!(store.compaction_threshold(), 0);
+
+    for i in 0..100 {
+        store.set(format!("key{}", i % 10), i.to_string()).unwrap();
+    }
+    let compactions = store.metrics().compactions();
+    assert!((1..50).contains(&compactions), "{} compactions for 100 writes", compactions);
+    for i in 0..10 {
+        assert_eq!(store.get(&format!("key{}", i)).unwrap(), Some((90 + i).to_string()));
+    }
+    drop(store);
+
+    // The threshold is kept for later opens.
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.compaction_threshold(), 0);
+    store.debug_verify().unwrap();
+}
+
+#[test]
+fn tiny_threshold_does_not_compact_every_write() {
+    let dir = TempDir::new().unwrap();
+    let options = KvStoreOptions::new().compaction_threshold(1);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+
+    // Distinct keys leave nothing stale to reclaim.
+    for i in 0..20 {
+        store.set(format!("key{}", i), "v".to_string()).unwrap();
+    }
+    assert_eq!(store.metrics().compactions(), 0);
+
+    // One stale record is past the threshold, but not yet a quarter of the log.
+    store.set("key0".to_string(), "w".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 0);
+    assert!(store.uncompacted_bytes() > 1);
+
+    // A large value going stale is, and after compacting it the next write
+    // doesn't compact again.
+    store.set("big".to_string(), "x".repeat(4096)).unwrap();
+    store.remove("big".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 1);
+    store.set("key1".to_string(), "w".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 1);
+}
+
+#[test]
+fn compaction_drops_tombstones_of_keys_set_again() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.remove("a".to_string()).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    store.set("b".to_string(), "1".to_string()).unwrap();
+    store.remove("b".to_string()).unwrap();
+
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set a=2"]);
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    assert_eq!(store.get("b").unwrap(), None);
+}
+
+#[test]
+fn tombstones_survive_compaction_within_grace() {
+    let dir = TempDir::new().unwrap();
+    let clock = MockClock::new(SystemTime::now());
+    let options = || KvStoreOptions::new().clock(clock.clone()).tombstone_grace(Duration::from_secs(3600));
+    let mut store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    for key in ["a", "b", "c"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+    store.remove("a".to_string()).unwrap();
+    store.remove("b".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+
+    // `b` was set again, so only `a`'s removal is kept, after the live keys.
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set b=2", "Set c=1", "Remove a"]);
+    store.debug_verify().unwrap();
+
+    clock.advance(Duration::from_secs(1800));
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set b=2", "Set c=1", "Remove a"]);
+
+    clock.advance(Duration::from_secs(1800));
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set b=2", "Set c=1"]);
+
+    // Removals found at open start their grace period then.
+    store.remove("c".to_string()).unwrap();
+    drop(store);
+    clock.advance(Duration::from_secs(7200));
+    let mut store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set b=2", "Remove c"]);
+    assert_eq!(store.get("c").unwrap(), None);
+}
+
+#[test]
+fn compaction_report_matches_the_file_size_change() {
+    let dir = TempDir::new().unwrap();
+    let log_len = || fs::metadata(dir.path().join("store.log")).unwrap().len();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..50 {
+        store.set(format!("key{}", i % 5), i.to_string()).unwrap();
+    }
+    store.remove("key4".to_string()).unwrap();
+
+    let before = log_len();
+    let report = store.compact().unwrap();
+    assert_eq!(report.bytes_before(), before);
+    assert_eq!(report.bytes_after(), log_len());
+    assert_eq!(report.bytes_freed(), before - log_len());
+    assert_eq!(report.entries_retained(), 4);
+
+    // Compacting a compacted log frees nothing.
+    let report = store.compact().unwrap();
+    assert_eq!((report.bytes_freed(), report.entries_retained()), (0, 4));
+}
+
+#[test]
+fn estimate_matches_what_compaction_reclaims() {
+    let dir = TempDir::new().unwrap();
+    let log_len = || fs::metadata(dir.path().join("store.log")).unwrap().len();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.estimate_compaction().bytes_saved(), 0);
+    assert_eq!(store.estimate_compaction().fragmentation_ratio(), 0.0);
+
+    for i in 0..40 {
+        store.set(format!("key{}", i % 8), "v".repeat(i)).unwrap();
+    }
+    store.remove("key0".to_string()).unwrap();
+    store.remove("key1".to_string()).unwrap();
+
+    let before = log_len();
+    let estimate = store.estimate_compaction();
+    assert_eq!(log_len(), before);
+    assert_eq!(estimate.log_bytes(), before);
+    let report = store.compact().unwrap();
+    let margin = before / 100;
+    assert!(estimate.bytes_saved().abs_diff(report.bytes_freed()) <= margin, "{:?} vs {:?}", estimate, report);
+    assert!(estimate.projected_bytes().abs_diff(report.bytes_after()) <= margin, "{:?} vs {:?}", estimate, report);
+    assert!(estimate.fragmentation_ratio() > 0.5);
+    assert_eq!(store.estimate_compaction().bytes_saved(), 0);
+}
+
+#[test]
+fn threshold_set_on_a_store_survives_reopen() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.compaction_threshold(), 1024 * 1024);
+    store.set_compaction_threshold(4096);
+    drop(store);
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.compaction_threshold(), 4096);
+    drop(store);
+
+    // An option overrides the saved value for that open only.
+    let store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().compaction_threshold(100)).unwrap();
+    assert_eq!(store.compaction_threshold(), 100);
+    drop(store);
+    assert_eq!(KvStore::open(dir.path()).unwrap().compaction_threshold(), 4096);
+
+    // A damaged meta file falls back to the default.
+    fs::write(dir.path().join("store.meta"), "not json").unwrap();
+    assert_eq!(KvStore::open(dir.path()).unwrap().compaction_threshold(), 1024 * 1024);
+}
+
+#[test]
+fn compaction_builds_in_the_temp_dir() {
+    let dir = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let temp_dir = scratch.path().join("compact");
+    let options = || KvStoreOptions::new().compaction_temp_dir(&temp_dir);
+    let mut store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    for i in 0..20 {
+        store.set(format!("key{}", i % 4), i.to_string()).unwrap();
+    }
+    assert!(store.estimate_compaction().free_bytes().is_some());
+
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set key0=16", "Set key1=17", "Set key2=18", "Set key3=19"]);
+    // The temp dir is created on demand and left empty.
+    assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+    let mut names: Vec<String> =
+        fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    names.sort();
+    assert_eq!(names, ["store.compacted", "store.hint", "store.log"]);
+
+    store.set("key0".to_string(), "20".to_string()).unwrap();
+    drop(store);
+    let store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    assert_eq!(store.get("key0").unwrap().as_deref(), Some("20"));
+    store.debug_verify().unwrap();
+}
+
+#[test]
+fn compaction_rewrites_the_marker_when_done() {
+    let dir = TempDir::new().unwrap();
+    let marker = dir.path().join("store.compacted");
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().clock(clock.clone())).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    assert!(!marker.exists());
+
+    store.compact().unwrap();
+    assert_eq!(fs::read_to_string(&marker).unwrap(), "1700000000000\n");
+    let modified = fs::metadata(&marker).unwrap().modified().unwrap();
+
+    thread::sleep(Duration::from_millis(20));
+    clock.advance(Duration::from_secs(60));
+    store.compact().unwrap();
+    assert_eq!(fs::read_to_string(&marker).unwrap(), "1700000060000\n");
+    assert!(fs::metadata(&marker).unwrap().modified().unwrap() > modified);
+}
+
+#[test]
+fn ceiling_bounds_the_log_under_overwrites() {
+    let dir = TempDir::new().unwrap();
+    let log_len = || fs::metadata(dir.path().join("store.log")).unwrap().len();
+    // The threshold alone would let the log reach 1GB.
+    let options = KvStoreOptions::new().compaction_threshold(1 << 30).uncompacted_ceiling(10_000);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+
+    let mut largest = 0;
+    for i in 0..2000 {
+        store.set(format!("key{}", i % 3), format!("{:0100}", i)).unwrap();
+        largest = largest.max(log_len());
+    }
+    assert!(store.metrics().compactions() > 0);
+    // Stale data stays under the ceiling, plus the three live records and
+    // the write that crossed it.
+    assert!(largest < 10_000 + 4 * 200, "log reached {} bytes", largest);
+    assert_eq!(store.get("key1").unwrap(), Some(format!("{:0100}", 1999)));
+}
+
+#[test]
+fn ceiling_refuses_writes_left_to_manual_compaction() {
+    let dir = TempDir::new().unwrap();
+    let options = KvStoreOptions::new().auto_compaction(false).uncompacted_ceiling(2_000);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+
+    let mut refused = None;
+    for i in 0..100 {
+        match store.set("key".to_string(), format!("{:0100}", i)) {
+            Ok(()) => {}
+            Err(KvError::Backpressure { uncompacted, ceiling }) => {
+                assert!(uncompacted > ceiling);
+                assert_eq!(ceiling, 2_000);
+                refused = Some(i);
+                break;
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+    let refused = refused.expect("writes were never refused");
+    assert_eq!(store.get("key").unwrap(), Some(format!("{:0100}", refused - 1)));
+
+    store.compact().unwrap();
+    store.set("key".to_string(), "after".to_string()).unwrap();
+    assert_eq!(store.metrics().compactions(), 1);
+}
+
+#[test]
+fn defragment_sorts_a_log_with_nothing_stale() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["q", "b", "x", "a", "m"] {
+        store.set(key.to_string(), key.repeat(3)).unwrap();
+    }
+    assert_eq!(store.uncompacted_bytes(), 0);
+    let before = fs::metadata(dir.path().join("store.log")).unwrap().len();
+
+    store.defragment().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set a=aaa", "Set b=bbb", "Set m=mmm", "Set q=qqq", "Set x=xxx"]);
+    assert_eq!(fs::metadata(dir.path().join("store.log")).unwrap().len(), before);
+    for key in ["q", "b", "x", "a", "m"] {
+        assert_eq!(store.get(key).unwrap(), Some(key.repeat(3)));
+    }
+    drop(store);
+    KvStore::open(dir.path()).unwrap().debug_verify().unwrap();
+}
+
+#[test]
+fn without_auto_compaction_the_log_grows_until_compacted() {
+    let dir = TempDir::new().unwrap();
+    let log_len = || fs::metadata(dir.path().join("store.log")).unwrap().len();
+    let options = KvStoreOptions::new().auto_compaction(false).compaction_threshold(1).max_log_age(Duration::ZERO);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+
+    let mut last = 0;
+    for i in 0..500 {
+        store.set("key".to_string(), format!("{:0100}", i)).unwrap();
+        assert!(log_len() > last);
+        last = log_len();
+    }
+    assert_eq!(store.metrics().compactions(), 0);
+    assert!(store.uncompacted_bytes() > 499 * 100);
+
+    store.compact().unwrap();
+    assert_eq!(log_records(dir.path()).len(), 1);
+    assert!(log_len() < last / 100);
+}
+
+#[test]
+fn fragmentation_ratio_is_the_stale_share_of_the_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "22".to_string()).unwrap();
+    store.set("a".to_string(), "333".to_string()).unwrap();
+    store.set("b".to_string(), "4444".to_string()).unwrap();
+    store.set("c".to_string(), "55555".to_string()).unwrap();
+
+    // The first two records are shadowed.
+    let lines = log_lines(dir.path());
+    let stale = (lines[0].len() + lines[1].len()) as f64;
+    let total = lines.concat().len() as f64;
+    let estimate = store.estimate_compaction();
+    assert!((estimate.fragmentation_ratio() - stale / total).abs() < 1e-9, "{}", estimate.fragmentation_ratio());
+    if cfg!(unix) {
+        assert!(estimate.free_bytes().unwrap() > 0);
+    }
+
+    store.compact().unwrap();
+    assert_eq!(store.estimate_compaction().fragmentation_ratio(), 0.0);
+}
@@ -0,0 +1,290 @@
+mod common;
+
+use common::FailAfter;
+use kvstore::{KvStore, KvStoreOptions};
+use std::collections::HashMap;
+use tempfile::TempDir;
+
+/// One acknowledged operation of the workload.
+enum Op {
+    Set(&'static str, String),
+    Remove(&'static str),
+    /// Applied atomically: `None` removes the key.
+    Txn(Vec<(&'static str, Option<String>)>),
+}
+
+const KEYS: &[&str] = &["alpha", "beta", "gamma", "delta"];
+
+/// The expected contents after each operation, with the log length then.
+type States = Vec<(u64, HashMap<&'static str, String>)>;
+
+fn workload() -> Vec<Op> {
+    let mut ops = Vec::new();
+    for round in 0..4 {
+        ops.push(Op::Set("alpha", format!("a{}", round)));
+        ops.push(Op::Set("beta", format!("b{}", round)));
+        ops.push(Op::Txn(vec![
+            ("gamma", Some(format!("g{}", round))),
+            ("alpha", None),
+            ("delta", Some(format!("d{}", round))),
+        ]));
+        ops.push(Op::Remove("beta"));
+        ops.push(Op::Set("alpha", format!("a{}-again", round)));
+    }
+    ops
+}
+
+fn apply(store: &mut KvStore, model: &mut HashMap<&'static str, String>, op: &Op) -> kvstore::Result<()> {
+    match op {
+        Op::Set(key, val) => {
+            store.set(key.to_string(), val.clone())?;
+            model.insert(key, val.clone());
+        }
+        Op::Remove(key) => {
+            store.remove(key.to_string())?;
+            model.remove(key);
+        }
+        Op::Txn(writes) => {
+            let mut txn = store.transaction();
+            for (key, val) in writes {
+                match val {
+                    Some(val) => {
+                        txn.set(key.to_string(), val.clone());
+                        model.insert(key, val.clone());
+                    }
+                    None => {
+                        txn.remove(key.to_string());
+                        model.remove(key);
+                    }
+                }
+            }
+            txn.commit()?;
+        }
+    }
+    Ok(())
+}
+
+fn check(store: &KvStore, expected: &HashMap<&'static str, String>, cut: usize) -> kvstore::Result<()> {
+    for key in KEYS {
+        let actual = store.get(key)?;
+        assert_eq!(
+            actual.as_ref(),
+            expected.get(key),
+            "crash after {} bytes: wrong value for {}",
+            cut,
+            key
+        );
+    }
+    Ok(())
+}
+
+/// Runs the workload on a fresh store, returning its log and the state after
+/// every operation.
+fn record() -> kvstore::Result<(Vec<u8>, States)> {
+    let source = TempDir::new()?;
+    let mut store = KvStore::open_with_options(source.path(), KvStoreOptions::new().auto_compaction(false))?;
+
+    let mut model = HashMap::new();
+    let mut states = vec![(0u64, model.clone())];
+    for op in workload() {
+        apply(&mut store, &mut model, &op)?;
+        let len = std::fs::metadata(source.path().join("store.log"))?.len();
+        states.push((len, model.clone()));
+    }
+    drop(store);
+
+    Ok((std::fs::read(source.path().join("store.log"))?, states))
+}
+
+/// Simulates a crash at every byte of the log: the log is cut off there, the
+/// store reopened, and it must hold exactly the operations whose records were
+/// complete at the cut (transactions all or nothing). A write after recovery
+/// must then survive another reopen, so recovery leaves a clean tail.
+#[test]
+fn crash_at_every_byte_recovers_a_valid_prefix() -> kvstore::Result<()> {
+    let (log, states) = record()?;
+
+    for cut in 0..=log.len() {
+        let expected = &states
+            .iter()
+            .rev()
+            .find(|(len, _)| *len <= cut as u64)
+            .expect("state for empty log")
+            .1;
+
+        let dir = TempDir::new()?;
+        std::fs::write(dir.path().join("store.log"), &log[..cut])?;
+
+        let mut store = KvStore::open(dir.path())?;
+        store.debug_verify()?;
+        check(&store, expected, cut)?;
+
+        store.set("after_crash".to_string(), "ok".to_string())?;
+        drop(store);
+
+        let store = KvStore::open(dir.path())?;
+        store.debug_verify()?;
+        check(&store, expected, cut)?;
+        assert_eq!(store.get("after_crash")?.as_deref(), Some("ok"), "crash after {} bytes: write after recovery lost", cut);
+    }
+    Ok(())
+}
+
+/// Simulates a crash during compaction at every byte of the compacted copy:
+/// the full log is intact beside a partial `store.log.compact`. Reopening must
+/// give the final state, and a compaction then has to run to completion.
+#[test]
+fn interrupted_compaction_at_every_byte_recovers() -> kvstore::Result<()> {
+    let (log, states) = record()?;
+    let expected = &states.last().expect("final state").1;
+
+    let source = TempDir::new()?;
+    std::fs::write(source.path().join("store.log"), &log)?;
+    KvStore::open(source.path())?.compact()?;
+    let compacted = std::fs::read(source.path().join("store.log"))?;
+
+    for cut in 0..=compacted.len() {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.path().join("store.log"), &log)?;
+        std::fs::write(dir.path().join("store.log.compact"), &compacted[..cut])?;
+
+        let mut store = KvStore::open(dir.path())?;
+        store.debug_verify()?;
+        check(&store, expected, cut)?;
+        assert!(!dir.path().join("store.log.compact").exists(), "compaction cut at {}: partial copy left behind", cut);
+
+        store.compact()?;
+        drop(store);
+
+        let store = KvStore::open(dir.path())?;
+        store.debug_verify()?;
+        check(&store, expected, cut)?;
+        assert_eq!(std::fs::read(dir.path().join("store.log"))?, compacted, "compaction cut at {}: rerun differs", cut);
+    }
+    Ok(())
+}
+
+/// A crash between a compaction's `store.compacted` marker and the cleanup of
+/// an older run can leave complete compacted copies behind, in both the
+/// foreground and background locations, from an earlier state of the log.
+/// The log is still the only source of truth: reopening must give its final
+/// state and discard the copies.
+#[test]
+fn stale_compacted_copies_beside_the_marker_are_discarded() -> kvstore::Result<()> {
+    let (log, states) = record()?;
+    let expected = &states.last().expect("final state").1;
+
+    // A compaction of the log as it was halfway through the workload.
+    let (half_len, _) = states[states.len() / 2];
+    let source = TempDir::new()?;
+    std::fs::write(source.path().join("store.log"), &log[..half_len as usize])?;
+    KvStore::open(source.path())?.compact()?;
+    let stale = std::fs::read(source.path().join("store.log"))?;
+    let marker = std::fs::read(source.path().join("store.compacted"))?;
+
+    let dir = TempDir::new()?;
+    std::fs::write(dir.path().join("store.log"), &log)?;
+    std::fs::write(dir.path().join("store.log.compact"), &stale)?;
+    std::fs::write(dir.path().join("store.log.compact.bg"), &stale[..stale.len() / 2])?;
+    std::fs::write(dir.path().join("store.compacted"), &marker)?;
+
+    let store = KvStore::open(dir.path())?;
+    store.debug_verify()?;
+    check(&store, expected, log.len())?;
+    let contents: HashMap<String, String> = store.scan_glob("*")?.into_iter().collect();
+    let expected_contents: HashMap<String, String> = expected.iter().map(|(key, val)| (key.to_string(), val.clone())).collect();
+    assert_eq!(contents, expected_contents);
+    for name in ["store.log.compact", "store.log.compact.bg"] {
+        assert!(!dir.path().join(name).exists(), "{} left behind", name);
+    }
+    assert_eq!(std::fs::read(dir.path().join("store.log"))?, log);
+    Ok(())
+}
+
+/// Fails every write of the workload at every byte budget, through the
+/// store's `Storage`: the write runs out of room after `budget` bytes of its
+/// record. A failed write must leave the store, and the store reopened from
+/// disk, as they were before it; one that succeeds, as after it. Writes
+/// after the failure must then go through.
+#[test]
+fn failed_write_at_every_byte_budget_is_all_or_nothing() -> kvstore::Result<()> {
+    let (_, states) = record()?;
+    let ops = workload();
+
+    for (i, op) in ops.iter().enumerate() {
+        let record_len = (states[i + 1].0 - states[i].0) as usize;
+        for budget in 0..=record_len {
+            let dir = TempDir::new()?;
+            let storage = FailAfter::default();
+            let options = KvStoreOptions::new().auto_compaction(false).storage(storage.clone());
+            let mut store = KvStore::open_with_options(dir.path(), options)?;
+            let mut model = HashMap::new();
+            for op in &ops[..i] {
+                apply(&mut store, &mut model, op)?;
+            }
+
+            storage.limit(budget);
+            let mut attempted = model.clone();
+            let expected = match apply(&mut store, &mut attempted, op) {
+                Ok(()) => attempted,
+                Err(_) => model,
+            };
+            assert_eq!(budget == record_len, expected == states[i + 1].1, "op {} failed at {} bytes", i, budget);
+            store.debug_verify()?;
+            check(&store, &expected, budget)?;
+
+            storage.unlimit();
+            store.set("after_failure".to_string(), "ok".to_string())?;
+            drop(store);
+
+            let store = KvStore::open(dir.path())?;
+            store.debug_verify()?;
+            check(&store, &expected, budget)?;
+            assert_eq!(store.get("after_failure")?.as_deref(), Some("ok"), "op {} failed at {} bytes: later write lost", i, budget);
+        }
+    }
+    Ok(())
+}
+
+/// Fails compaction at every byte budget of the compacted copy, through the
+/// store's `Storage`. The log must be left as it was, both in the open store
+/// and reopened, the partial copy removed by the reopen, and the next
+/// compaction must produce the same log as an uninterrupted one.
+#[test]
+fn failed_compaction_at_every_byte_budget_leaves_the_log() -> kvstore::Result<()> {
+    let (log, states) = record()?;
+    let expected = &states.last().expect("final state").1;
+
+    let source = TempDir::new()?;
+    std::fs::write(source.path().join("store.log"), &log)?;
+    KvStore::open(source.path())?.compact()?;
+    let compacted = std::fs::read(source.path().join("store.log"))?;
+
+    for budget in 0..=compacted.len() {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.path().join("store.log"), &log)?;
+        let storage = FailAfter::default();
+        let options = KvStoreOptions::new().auto_compaction(false).storage(storage.clone());
+        let mut store = KvStore::open_with_options(dir.path(), options)?;
+
+        storage.limit(budget);
+        let result = store.compact();
+        assert_eq!(result.is_ok(), budget == compacted.len(), "compaction with {} bytes", budget);
+        store.debug_verify()?;
+        check(&store, expected, budget)?;
+        if result.is_err() {
+            assert_eq!(std::fs::read(dir.path().join("store.log"))?, log, "compaction failed at {}: log changed", budget);
+        }
+        drop(store);
+
+        let mut store = KvStore::open(dir.path())?;
+        store.debug_verify()?;
+        check(&store, expected, budget)?;
+        assert!(!dir.path().join("store.log.compact").exists(), "compaction failed at {}: partial copy left behind", budget);
+
+        store.compact()?;
+        drop(store);
+        assert_eq!(std::fs::read(dir.path().join("store.log"))?, compacted, "compaction failed at {}: rerun differs", budget);
+    }
+    Ok(())
+}
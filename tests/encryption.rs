@@ -0,0 +1,60 @@
+use std::fs;
+
+use kvstore::{KvError, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+const KEY: [u8; 32] = [7; 32];
+
+fn encrypted(key: [u8; 32]) -> KvStoreOptions {
+    KvStoreOptions::new().encryption_key(key)
+}
+
+#[test]
+fn values_round_trip_encrypted() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), encrypted(KEY)).unwrap();
+    store.set("card".to_string(), "4111-1111".to_string()).unwrap();
+    store.set("pin".to_string(), "0000".to_string()).unwrap();
+    store.set("pin".to_string(), "1234".to_string()).unwrap();
+    assert_eq!(store.get("card").unwrap().as_deref(), Some("4111-1111"));
+
+    // Only the values are hidden, and compaction re-reads them with the key.
+    let log = fs::read_to_string(dir.path().join("store.log")).unwrap();
+    assert!(!log.contains("4111") && !log.contains("1234"), "{}", log);
+    assert!(log.contains("\"card\""));
+    store.compact().unwrap();
+    drop(store);
+
+    let store = KvStore::open_with_options(dir.path(), encrypted(KEY)).unwrap();
+    assert_eq!(store.get("card").unwrap().as_deref(), Some("4111-1111"));
+    assert_eq!(store.get("pin").unwrap().as_deref(), Some("1234"));
+}
+
+#[test]
+fn wrong_or_missing_key_fails_to_decrypt() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), encrypted(KEY)).unwrap();
+    store.set("a".to_string(), "secret".to_string()).unwrap();
+    drop(store);
+
+    // The index is rebuilt without the key; only reading values needs it.
+    let store = KvStore::open_with_options(dir.path(), encrypted([8; 32])).unwrap();
+    assert_eq!(store.len().unwrap(), 1);
+    assert!(matches!(store.get("a"), Err(KvError::Decryption(_))));
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert!(matches!(store.get("a"), Err(KvError::Decryption(_))));
+}
+
+#[test]
+fn plaintext_values_stay_readable_with_a_key() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("old".to_string(), "plain".to_string()).unwrap();
+    drop(store);
+
+    let mut store = KvStore::open_with_options(dir.path(), encrypted(KEY)).unwrap();
+    assert_eq!(store.get("old").unwrap().as_deref(), Some("plain"));
+    store.set("new".to_string(), "hidden".to_string()).unwrap();
+    assert_eq!(store.get("new").unwrap().as_deref(), Some("hidden"));
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::log_records;
+use kvstore::{KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+fn cold() -> HashMap<String, String> {
+    HashMap::from([("cold".to_string(), "from below".to_string()), ("both".to_string(), "below".to_string())])
+}
+
+#[test]
+fn local_miss_reads_from_the_fallback() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().fallback(cold())).unwrap();
+    store.set("both".to_string(), "here".to_string()).unwrap();
+
+    assert_eq!(store.get("cold").unwrap().as_deref(), Some("from below"));
+    assert_eq!(store.get("both").unwrap().as_deref(), Some("here"));
+    assert_eq!(store.get("missing").unwrap(), None);
+    assert!(store.contains_key("cold").unwrap());
+
+    // A plain read doesn't copy the value here.
+    assert_eq!(store.pointer("cold").unwrap(), None);
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set both=here"]);
+}
+
+#[test]
+fn promoted_value_becomes_a_local_hit() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().fallback(cold())).unwrap();
+
+    assert_eq!(store.get_and_promote("cold").unwrap().as_deref(), Some("from below"));
+    assert!(store.pointer("cold").unwrap().is_some());
+    assert_eq!(store.get_and_promote("missing").unwrap(), None);
+    drop(store);
+
+    // Without the fallback the promoted value is still here.
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("cold").unwrap().as_deref(), Some("from below"));
+    assert_eq!(store.get("both").unwrap(), None);
+}
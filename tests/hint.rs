@@ -0,0 +1,119 @@
+use std::fs;
+
+use kvstore::{HintFormat, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+fn assert_quick_stats_match(dir: &TempDir, from_hint: bool) {
+    let stats = KvStore::quick_stats(dir.path()).unwrap();
+    assert_eq!(stats.from_hint(), from_hint);
+    assert_eq!(stats.log_bytes(), fs::metadata(dir.path().join("store.log")).unwrap().len());
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(stats.estimated_keys(), store.len().unwrap() as u64);
+}
+
+#[test]
+fn quick_stats_without_hint_replays_the_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..20 {
+        store.set(format!("key{}", i % 15), i.to_string()).unwrap();
+    }
+    store.remove("key3".to_string()).unwrap();
+    drop(store);
+
+    assert!(!dir.path().join("store.hint").exists());
+    assert_quick_stats_match(&dir, false);
+}
+
+#[test]
+fn quick_stats_counts_from_hint_and_log_tail() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..10 {
+        store.set(format!("key{}", i), "v".to_string()).unwrap();
+    }
+    store.compact().unwrap();
+    drop(store);
+    assert!(dir.path().join("store.hint").exists());
+    assert_quick_stats_match(&dir, true);
+
+    // Keys added and removed since the compaction are counted from the tail.
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("key10".to_string(), "v".to_string()).unwrap();
+    store.set("key0".to_string(), "w".to_string()).unwrap();
+    store.remove("key1".to_string()).unwrap();
+    store.remove("key10".to_string()).unwrap();
+    drop(store);
+    assert_quick_stats_match(&dir, true);
+}
+
+#[test]
+fn quick_stats_ignores_hint_longer_than_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..10 {
+        store.set(format!("key{}", i), "v".to_string()).unwrap();
+    }
+    store.compact().unwrap();
+    drop(store);
+
+    // A log shorter than the hint describes isn't the log it was written for.
+    let log = fs::read_to_string(dir.path().join("store.log")).unwrap();
+    let first_line = log.split_inclusive('\n').next().unwrap();
+    fs::write(dir.path().join("store.log"), first_line).unwrap();
+    assert_quick_stats_match(&dir, false);
+}
+
+#[test]
+fn quick_stats_reads_a_store_held_open() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+
+    let stats = KvStore::quick_stats(dir.path()).unwrap();
+    assert_eq!(stats.estimated_keys(), 2);
+    store.set("c".to_string(), "3".to_string()).unwrap();
+}
+
+fn compacted_with_hint(dir: &TempDir, format: HintFormat) {
+    let options = KvStoreOptions::new().hint_format(format);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    for i in 0..2000 {
+        store.set(format!("user:{:05}", i), "v".to_string()).unwrap();
+    }
+    store.compact().unwrap();
+    // Removals after the compaction need the hint's keys to be counted.
+    for i in 0..10 {
+        store.remove(format!("user:{:05}", i * 7)).unwrap();
+    }
+}
+
+#[test]
+fn binary_hint_reloads_every_key() {
+    let json = TempDir::new().unwrap();
+    compacted_with_hint(&json, HintFormat::Json);
+    let binary = TempDir::new().unwrap();
+    compacted_with_hint(&binary, HintFormat::Binary);
+
+    let hint = fs::read(binary.path().join("store.hint")).unwrap();
+    assert_eq!(&hint[..4], b"KVH\x01");
+    let json_len = fs::metadata(json.path().join("store.hint")).unwrap().len();
+    assert!((hint.len() as u64) < json_len * 3 / 4, "{} vs {} bytes", hint.len(), json_len);
+
+    assert_quick_stats_match(&json, true);
+    assert_quick_stats_match(&binary, true);
+    assert_eq!(KvStore::quick_stats(binary.path()).unwrap().estimated_keys(), 1990);
+}
+
+#[test]
+fn binary_hint_of_unknown_version_is_ignored() {
+    let dir = TempDir::new().unwrap();
+    compacted_with_hint(&dir, HintFormat::Binary);
+    let path = dir.path().join("store.hint");
+    let mut hint = fs::read(&path).unwrap();
+    hint[3] = 2;
+    fs::write(&path, hint).unwrap();
+
+    assert_quick_stats_match(&dir, false);
+}
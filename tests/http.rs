@@ -0,0 +1,209 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use kvstore::client::Client;
+use kvstore::server::Server;
+use kvstore::{KvError, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+/// A server on `addr` over the store in `dir`, run on its own thread.
+fn serve(dir: &TempDir, addr: &str) -> (Arc<Server>, JoinHandle<()>) {
+    let server = Arc::new(Server::bind(addr, KvStore::open(dir.path()).unwrap()).unwrap());
+    let running = Arc::clone(&server);
+    let thread = thread::spawn(move || running.run().unwrap());
+    (server, thread)
+}
+
+fn stop(server: Arc<Server>, thread: JoinHandle<()>) {
+    server.shutdown().unwrap();
+    thread.join().unwrap();
+}
+
+/// Sends one request on its own connection, returning the status and body.
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body)
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+fn client_drives_server() {
+    let dir = TempDir::new().unwrap();
+    let (server, thread) = serve(&dir, "127.0.0.1:0");
+    let mut client = Client::connect(server.local_addr().unwrap()).unwrap();
+
+    client.set("user:1", "alice").unwrap();
+    client.set("user:2", "bob").unwrap();
+    client.set("other", "x").unwrap();
+    client.set("spaced key/é", "ünïcode").unwrap();
+    assert_eq!(client.get("user:1").unwrap().as_deref(), Some("alice"));
+    assert_eq!(client.get("spaced key/é").unwrap().as_deref(), Some("ünïcode"));
+    assert_eq!(client.get("missing").unwrap(), None);
+    assert_eq!(
+        client.scan("user:").unwrap(),
+        [("user:1".to_string(), "alice".to_string()), ("user:2".to_string(), "bob".to_string())]
+    );
+
+    client.remove("user:1").unwrap();
+    assert_eq!(client.get("user:1").unwrap(), None);
+    assert!(matches!(client.remove("user:1"), Err(KvError::KeyNotFound)));
+    assert!(matches!(client.set("", "v"), Err(KvError::InvalidKey(_))));
+
+    stop(server, thread);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("user:2").unwrap().as_deref(), Some("bob"));
+    assert_eq!(store.get("user:1").unwrap(), None);
+}
+
+#[test]
+fn client_reconnects_after_server_restart() {
+    let dir = TempDir::new().unwrap();
+    let (server, thread) = serve(&dir, "127.0.0.1:0");
+    let addr = server.local_addr().unwrap();
+    let mut client = Client::connect(addr).unwrap();
+    client.set("a", "1").unwrap();
+
+    // The client's kept-alive connection dies with the server.
+    stop(server, thread);
+    let (server, thread) = serve(&dir, &addr.to_string());
+
+    assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+    client.set("b", "2").unwrap();
+    assert_eq!(client.get("b").unwrap().as_deref(), Some("2"));
+    stop(server, thread);
+}
+
+#[test]
+fn client_reports_server_down() {
+    let dir = TempDir::new().unwrap();
+    let (server, thread) = serve(&dir, "127.0.0.1:0");
+    let mut client = Client::connect(server.local_addr().unwrap()).unwrap();
+    stop(server, thread);
+
+    assert!(matches!(client.get("a"), Err(KvError::Io(_))));
+    assert!(matches!(client.get("a"), Err(KvError::Io(_))));
+}
+
+#[test]
+fn shutdown_persists_every_acknowledged_write() {
+    let dir = TempDir::new().unwrap();
+    let mut server = Server::bind("127.0.0.1:0", KvStore::open(dir.path()).unwrap()).unwrap();
+    server.set_write_window(Duration::from_millis(20));
+    let server = Arc::new(server);
+    let running = Arc::clone(&server);
+    let thread = thread::spawn(move || running.run());
+    let addr = server.local_addr().unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|w| {
+            thread::spawn(move || {
+                for i in 0..10 {
+                    assert_eq!(request(addr, "PUT", &format!("/kv/k{}-{}", w, i), &i.to_string()).0, 204);
+                }
+            })
+        })
+        .collect();
+    // A kept-alive connection still open at shutdown doesn't hold it up.
+    let mut idle = Client::connect(addr).unwrap();
+    idle.set("idle", "1").unwrap();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    server.shutdown().unwrap();
+    thread.join().unwrap().unwrap();
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.len().unwrap(), 41);
+    assert_eq!(store.get("k3-9").unwrap().as_deref(), Some("9"));
+}
+
+#[test]
+fn endpoints_map_store_results_to_statuses() {
+    let dir = TempDir::new().unwrap();
+    let (server, thread) = serve(&dir, "127.0.0.1:0");
+    let addr = server.local_addr().unwrap();
+
+    assert_eq!(request(addr, "PUT", "/kv/a%20b", "hello"), (204, String::new()));
+    assert_eq!(request(addr, "GET", "/kv/a%20b", ""), (200, "hello".to_string()));
+    assert_eq!(request(addr, "GET", "/kv/missing", ""), (404, r#"{"error":"Key not found"}"#.to_string()));
+    assert_eq!(request(addr, "DELETE", "/kv/a%20b", ""), (204, String::new()));
+    assert_eq!(request(addr, "DELETE", "/kv/a%20b", "").0, 404);
+    assert_eq!(request(addr, "GET", "/kv/a%20b", "").0, 404);
+
+    assert_eq!(request(addr, "PUT", "/kv/", "v").0, 400);
+    assert_eq!(request(addr, "GET", "/kv/%zz", "").0, 400);
+    assert_eq!(request(addr, "POST", "/kv/a", "v").0, 405);
+    assert_eq!(request(addr, "GET", "/other", "").0, 404);
+    stop(server, thread);
+}
+
+#[test]
+fn corruption_is_a_500_with_the_offset() {
+    let dir = TempDir::new().unwrap();
+    let (server, thread) = serve(&dir, "127.0.0.1:0");
+    let addr = server.local_addr().unwrap();
+    request(addr, "PUT", "/kv/a", "1");
+    request(addr, "PUT", "/kv/b", "2");
+
+    // Overwrite b's record behind the server's back.
+    let path = dir.path().join("store.log");
+    let log = std::fs::read_to_string(&path).unwrap();
+    let offset = log.find('\n').unwrap() + 1;
+    let garbage = "x".repeat(log.len() - offset - 1) + "\n";
+    std::fs::write(&path, log[..offset].to_string() + &garbage).unwrap();
+
+    let (status, body) = request(addr, "GET", "/kv/b", "");
+    assert_eq!(status, 500);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["offset"], offset);
+    assert!(body["error"].as_str().unwrap().contains("corrupt"), "{}", body);
+    assert_eq!(request(addr, "GET", "/kv/a", ""), (200, "1".to_string()));
+    stop(server, thread);
+}
+
+#[test]
+fn scan_sees_a_consistent_view_while_others_write_and_compact() {
+    let dir = TempDir::new().unwrap();
+    let store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().compaction_threshold(4096)).unwrap();
+    let server = Arc::new(Server::bind("127.0.0.1:0", store).unwrap());
+    let running = Arc::clone(&server);
+    let thread = thread::spawn(move || running.run().unwrap());
+    let addr = server.local_addr().unwrap();
+
+    // Keys n0000, n0001, ... are written in order, each with its number as
+    // the value, while overwrites of another key keep compaction busy. Any
+    // consistent view holds an unbroken run of them.
+    const KEYS: usize = 200;
+    let writer = thread::spawn(move || {
+        let mut client = Client::connect(addr).unwrap();
+        for i in 0..KEYS {
+            client.set(&format!("n{:04}", i), &i.to_string()).unwrap();
+            client.set("churn", &"x".repeat(512)).unwrap();
+        }
+    });
+
+    let mut client = Client::connect(addr).unwrap();
+    let mut seen = 0;
+    while seen < KEYS {
+        let entries = client.scan("n").unwrap();
+        for (i, (key, val)) in entries.iter().enumerate() {
+            assert_eq!((key.as_str(), val.as_str()), (format!("n{:04}", i).as_str(), i.to_string().as_str()));
+        }
+        assert!(entries.len() >= seen, "scan went back from {} to {} keys", seen, entries.len());
+        seen = entries.len();
+    }
+    writer.join().unwrap();
+
+    stop(server, thread);
+    // Compaction did run meanwhile: the churn alone wrote far more than this.
+    assert!(std::fs::metadata(dir.path().join("store.log")).unwrap().len() < (KEYS * 512) as u64);
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use kvstore::cmd::Command;
+use kvstore::engine::read_log;
+use kvstore::{KvError, KvStore, KvStoreOptions, LogPointer};
+use tempfile::TempDir;
+
+/// A store holding `a` and `b`, with `c` set and removed, reopened from an
+/// index where `a` points at `b`'s record and `b` at the removal of `c`.
+/// Returns the store and the offsets of those two records.
+fn desynced(dir: &TempDir, options: KvStoreOptions) -> (KvStore, u64, u64) {
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    store.remove("c".to_string()).unwrap();
+    let b = store.pointer("b").unwrap().unwrap();
+    drop(store);
+
+    let log_path = dir.path().join("store.log");
+    let log_len = std::fs::metadata(&log_path).unwrap().len();
+    let (removal_offset, _) = read_log(&log_path)
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|(_, cmd)| matches!(cmd, Command::Remove { .. }))
+        .unwrap();
+    let removal = LogPointer::new(removal_offset, log_len - removal_offset);
+
+    let index = HashMap::from([("a".to_string(), b), ("b".to_string(), removal)]);
+    let store = KvStore::from_parts(dir.path(), options, index, log_len).unwrap();
+    (store, b.offset(), removal_offset)
+}
+
+#[test]
+fn get_catches_pointer_to_another_record() {
+    let dir = TempDir::new().unwrap();
+    let (store, b_offset, removal_offset) = desynced(&dir, KvStoreOptions::new());
+
+    assert!(matches!(store.get("a"), Err(KvError::LogCorruption(offset)) if offset == b_offset));
+    assert!(matches!(store.get("b"), Err(KvError::LogCorruption(offset)) if offset == removal_offset));
+    assert!(matches!(store.debug_verify(), Err(KvError::LogCorruption(_))));
+}
+
+#[test]
+fn debug_verify_catches_desynced_index() {
+    let dir = TempDir::new().unwrap();
+    let (store, b_offset, _) = desynced(&dir, KvStoreOptions::new());
+    assert!(matches!(store.debug_verify(), Err(KvError::LogCorruption(offset)) if offset == b_offset));
+    drop(store);
+
+    // A freshly replayed index agrees with the log, before and after compaction.
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.debug_verify().unwrap();
+    store.compact().unwrap();
+    store.debug_verify().unwrap();
+    let (a, b) = (store.pointer("a").unwrap().unwrap(), store.pointer("b").unwrap().unwrap());
+    drop(store);
+    let log_len = std::fs::metadata(dir.path().join("store.log")).unwrap().len();
+
+    // Right record, wrong length.
+    let short = LogPointer::new(a.offset(), a.len() - 1);
+    let index = HashMap::from([("a".to_string(), short), ("b".to_string(), b)]);
+    let store = KvStore::from_parts(dir.path(), KvStoreOptions::new(), index, log_len).unwrap();
+    assert!(matches!(store.debug_verify(), Err(KvError::LogCorruption(offset)) if offset == a.offset()));
+    drop(store);
+
+    // A live key missing from the index.
+    let index = HashMap::from([("a".to_string(), a)]);
+    let store = KvStore::from_parts(dir.path(), KvStoreOptions::new(), index, log_len).unwrap();
+    assert!(matches!(store.debug_verify(), Err(KvError::LogCorruption(offset)) if offset == b.offset()));
+}
+
+#[test]
+fn from_parts_serves_reads_from_the_given_index() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.set("a".to_string(), "3".to_string()).unwrap();
+    store.remove("b".to_string()).unwrap();
+    let (index, log_len) = store.export_index().unwrap();
+    drop(store);
+
+    let mut store = KvStore::from_parts(dir.path(), KvStoreOptions::new(), index.clone(), log_len).unwrap();
+    assert!(store.last_recovery().is_none());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("3"));
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(store.len().unwrap(), 1);
+    store.debug_verify().unwrap();
+
+    // Writes carry on from the end of the log.
+    store.set("c".to_string(), "4".to_string()).unwrap();
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("c").unwrap().as_deref(), Some("4"));
+    let new_len = store.export_index().unwrap().1;
+    drop(store);
+
+    // The index must describe the log as it is.
+    match KvStore::from_parts(dir.path(), KvStoreOptions::new(), index.clone(), log_len) {
+        Err(KvError::StaleIndex { index_len, log_len: actual }) => assert_eq!((index_len, actual), (log_len, new_len)),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    let past_end = HashMap::from([("a".to_string(), LogPointer::new(new_len - 1, 10))]);
+    assert!(matches!(
+        KvStore::from_parts(dir.path(), KvStoreOptions::new(), past_end, new_len),
+        Err(KvError::LogCorruption(offset)) if offset == new_len - 1
+    ));
+}
+
+#[test]
+fn tolerant_reads_treat_desynced_entries_as_misses() {
+    let dir = TempDir::new().unwrap();
+    let options = KvStoreOptions::new().tolerate_index_desync(true);
+    let (store, _, _) = desynced(&dir, options);
+
+    assert_eq!(store.get("a").unwrap(), None);
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(store.get_with_metadata("b").unwrap(), None);
+    assert!(matches!(store.debug_verify(), Err(KvError::LogCorruption(_))));
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::log_records;
+use kvstore::{KvError, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+fn app_prefix() -> KvStoreOptions {
+    KvStoreOptions::new().key_validator(|key| match key.starts_with("app:") {
+        true => Ok(()),
+        false => Err(KvError::InvalidKey(format!("{} lacks the app: prefix", key))),
+    })
+}
+
+#[test]
+fn keys_failing_the_validator_are_rejected_before_writing() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), app_prefix()).unwrap();
+
+    store.set("app:a".to_string(), "1".to_string()).unwrap();
+    match store.set("other:a".to_string(), "1".to_string()) {
+        Err(KvError::InvalidKey(reason)) => assert_eq!(reason, "other:a lacks the app: prefix"),
+        other => panic!("{:?}", other),
+    }
+    // The built-in check runs first.
+    match store.set(String::new(), "1".to_string()) {
+        Err(KvError::InvalidKey(reason)) => assert_eq!(reason, "Key cannot be empty"),
+        other => panic!("{:?}", other),
+    }
+
+    // One bad key fails the whole transaction.
+    let mut txn = store.transaction();
+    txn.set("app:b".to_string(), "2".to_string());
+    txn.set("b".to_string(), "2".to_string());
+    assert!(matches!(txn.commit(), Err(KvError::InvalidKey(_))));
+
+    assert_eq!(store.get("app:b").unwrap(), None);
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set app:a=1"]);
+}
+
+#[test]
+fn other_validator_errors_become_invalid_key() {
+    let dir = TempDir::new().unwrap();
+    let options = KvStoreOptions::new().key_validator(|_| Err(KvError::KeyNotFound));
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    match store.set("a".to_string(), "1".to_string()) {
+        Err(KvError::InvalidKey(reason)) => assert_eq!(reason, KvError::KeyNotFound.to_string()),
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::fs;
+
+use common::{log_lines, log_records};
+use kvstore::cmd::Command;
+use kvstore::engine::read_log;
+use kvstore::{KvError, KvStore};
+use tempfile::TempDir;
+
+#[test]
+fn read_log_streams_a_stores_records() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.remove("a".to_string()).unwrap();
+    drop(store);
+
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set b=2", "Remove a"]);
+
+    let log = fs::read_to_string(dir.path().join("store.log")).unwrap();
+    let offsets: Vec<u64> = read_log(&dir.path().join("store.log")).unwrap().map(|r| r.unwrap().0).collect();
+    let mut line_starts = Vec::new();
+    let mut pos = 0;
+    for line in log.split_inclusive('\n') {
+        line_starts.push(pos);
+        pos += line.len() as u64;
+    }
+    assert_eq!(offsets, line_starts);
+}
+
+#[test]
+fn read_log_reports_unparseable_records_and_goes_on() {
+    let dir = TempDir::new().unwrap();
+    KvStore::open(dir.path()).unwrap().set("a".to_string(), "1".to_string()).unwrap();
+    let path = dir.path().join("store.log");
+    let first = fs::read(&path).unwrap();
+    let mut log = first.clone();
+    log.extend_from_slice(b"not json\n");
+    log.extend_from_slice(&first);
+    fs::write(&path, &log).unwrap();
+
+    let records: Vec<_> = read_log(&path).unwrap().collect();
+    assert_eq!(records.len(), 3);
+    assert!(matches!(&records[0], Ok((0, Command::Set { key, .. })) if key == "a"));
+    assert!(matches!(records[1], Err(KvError::LogCorruption(offset)) if offset == first.len() as u64));
+    assert!(matches!(&records[2], Ok((offset, _)) if *offset == first.len() as u64 + 9));
+}
+
+#[test]
+fn recovery_report_lists_skipped_records() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["a", "b", "c", "d"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+    drop(store);
+
+    let mut lines = log_lines(dir.path());
+    let offset = |i: usize| lines[..i].concat().len() as u64;
+    let offsets = [offset(1), offset(3)];
+    for i in [1, 3] {
+        lines[i] = "#".repeat(lines[i].len() - 1) + "\n";
+    }
+    fs::write(dir.path().join("store.log"), lines.concat()).unwrap();
+
+    let store = KvStore::open(dir.path()).unwrap();
+    let report = store.last_recovery().unwrap();
+    assert_eq!(report.skipped_offsets(), offsets);
+    assert_eq!(report.recovered_bytes(), (lines[0].len() + lines[2].len()) as u64);
+    assert_eq!(report.truncated_at(), None);
+    assert!(report.discarded_transactions().is_empty());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("b").unwrap(), None);
+}
+
+#[test]
+fn recovery_report_records_truncated_tail() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    drop(store);
+
+    let lines = log_lines(dir.path());
+    let torn = lines[0].clone() + &lines[1][..lines[1].len() / 2];
+    fs::write(dir.path().join("store.log"), &torn).unwrap();
+
+    let store = KvStore::open(dir.path()).unwrap();
+    let report = store.last_recovery().unwrap();
+    assert_eq!(report.truncated_at(), Some(lines[0].len() as u64));
+    assert_eq!(report.skipped_offsets(), [lines[0].len() as u64]);
+    assert_eq!(fs::read_to_string(dir.path().join("store.log")).unwrap(), lines[0]);
+    assert_eq!(store.get("b").unwrap(), None);
+
+    // A clean open has nothing to report.
+    drop(store);
+    let report = KvStore::open(dir.path()).unwrap().last_recovery().cloned().unwrap();
+    assert_eq!(report.truncated_at(), None);
+}
+
+#[test]
+fn delimiter_and_control_bytes_round_trip() {
+    let pairs = [
+        ("line\nbreak", "first\nsecond\n"),
+        ("crlf\r\nkey", "windows\r\nline\r\n"),
+        ("quote\"key", "say \"hi\"\\n"),
+        ("nul\0key", "before\0after"),
+        ("plain", "\n"),
+    ];
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for (key, val) in pairs {
+        store.set(key.to_string(), format!("stale {}", val)).unwrap();
+        store.set(key.to_string(), val.to_string()).unwrap();
+    }
+    store.set("removed\nkey".to_string(), "x\0".to_string()).unwrap();
+    store.remove("removed\nkey".to_string()).unwrap();
+    let check = |store: &KvStore| {
+        for (key, val) in pairs {
+            assert_eq!(store.get(key).unwrap().as_deref(), Some(val), "{:?}", key);
+        }
+        assert_eq!(store.get("removed\nkey").unwrap(), None);
+        assert_eq!(store.len().unwrap(), pairs.len());
+    };
+    check(&store);
+
+    // JSON escapes them all, so every record is still a single line.
+    assert_eq!(log_lines(dir.path()).len(), 2 * pairs.len() + 2);
+    assert!(!fs::read(dir.path().join("store.log")).unwrap().contains(&0));
+
+    store.compact().unwrap();
+    check(&store);
+    assert_eq!(log_lines(dir.path()).len(), pairs.len());
+    drop(store);
+
+    let store = KvStore::open(dir.path()).unwrap();
+    check(&store);
+    store.debug_verify().unwrap();
+}
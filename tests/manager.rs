@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kvstore::{KvError, KvStoreOptions, StoreManager};
+use tempfile::TempDir;
+
+fn write_stale(manager: &StoreManager, name: &str, writes: usize) {
+    let store = manager.store(name).unwrap();
+    let mut store = store.write().unwrap();
+    for i in 0..writes {
+        store.set("key".to_string(), format!("{:0100}", i)).unwrap();
+    }
+}
+
+#[test]
+fn named_stores_are_isolated() {
+    let dir = TempDir::new().unwrap();
+    let manager = StoreManager::open(dir.path()).unwrap();
+    for name in ["users", "orders", "sessions"] {
+        let store = manager.store(name).unwrap();
+        store.write().unwrap().set("owner".to_string(), name.to_string()).unwrap();
+    }
+    manager.store("users").unwrap().write().unwrap().set("only_users".to_string(), "1".to_string()).unwrap();
+
+    assert_eq!(manager.names(), ["orders", "sessions", "users"]);
+    for name in ["users", "orders", "sessions"] {
+        let store = manager.store(name).unwrap();
+        let store = store.read().unwrap();
+        assert_eq!(store.get("owner").unwrap().as_deref(), Some(name));
+        assert_eq!(store.len().unwrap(), if name == "users" { 2 } else { 1 });
+        assert!(dir.path().join(name).join("store.log").exists());
+    }
+
+    // The same name hands out the same store.
+    assert!(Arc::ptr_eq(&manager.store("users").unwrap(), &manager.store("users").unwrap()));
+    assert_eq!(manager.total_metrics().sets(), 4);
+    assert_eq!(manager.metrics()["users"].sets(), 2);
+
+    for name in ["", ".", "..", "a/b", "a\\b"] {
+        assert!(matches!(manager.store(name), Err(KvError::InvalidStoreName(_))), "{:?}", name);
+    }
+}
+
+#[test]
+fn stores_share_the_compaction_worker() {
+    let dir = TempDir::new().unwrap();
+    let manager = StoreManager::open_with_options(dir.path(), KvStoreOptions::new().compaction_threshold(1000)).unwrap();
+    write_stale(&manager, "a", 2);
+    write_stale(&manager, "b", 50);
+    write_stale(&manager, "c", 50);
+
+    // Writes don't compact inline; the worker's check compacts every due
+    // store. The background thread may have run it first.
+    let compacted = manager.compact_due().unwrap();
+    assert!(compacted.iter().all(|name| name == "b" || name == "c"), "{:?}", compacted);
+    let metrics = manager.metrics();
+    let compactions: Vec<u64> = metrics.values().map(|m| m.compactions()).collect();
+    assert_eq!(compactions, [0, 1, 1]);
+    assert_eq!(manager.compact_due().unwrap(), Vec::<String>::new());
+
+    // Left alone, the background thread gets to it.
+    write_stale(&manager, "a", 50);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while manager.metrics()["a"].compactions() == 0 {
+        assert!(Instant::now() < deadline, "background compaction never ran");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(manager.total_metrics().compactions(), 3);
+    let store = manager.store("a").unwrap();
+    assert_eq!(store.read().unwrap().get("key").unwrap(), Some(format!("{:0100}", 49)));
+}
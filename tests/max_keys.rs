@@ -0,0 +1,118 @@
+mod common;
+
+use kvstore::{KvError, KvStore, KvStoreOptions, StoreManager};
+use tempfile::TempDir;
+
+fn open(dir: &TempDir, max_keys: usize) -> KvStore {
+    KvStore::open_with_options(dir.path(), KvStoreOptions::new().max_keys(max_keys)).unwrap()
+}
+
+#[test]
+fn entry_of_absent_key_is_not_tracked_for_eviction() {
+    let dir = TempDir::new().unwrap();
+    let mut store = open(&dir, 1);
+    for i in 0..3 {
+        let entry = store.entry(format!("ghost{}", i)).unwrap().and_modify(|v| v.push('x')).unwrap();
+        assert_eq!(entry.get(), None);
+    }
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+
+    assert_eq!(common::log_records(dir.path()), ["Set a=1", "Remove a", "Set b=2"]);
+    let uncompacted = store.uncompacted_bytes();
+    drop(store);
+    assert_eq!(KvStore::open(dir.path()).unwrap().uncompacted_bytes(), uncompacted);
+}
+
+#[test]
+fn entry_insert_counts_as_use() {
+    let dir = TempDir::new().unwrap();
+    let mut store = open(&dir, 2);
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.entry("b".to_string()).unwrap().or_insert("2".to_string()).unwrap();
+    store.get("a").unwrap();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("c").unwrap().as_deref(), Some("3"));
+}
+
+#[test]
+fn evicts_least_recently_used_key() {
+    let dir = TempDir::new().unwrap();
+    let mut store = open(&dir, 2);
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.get("a").unwrap();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+
+    assert_eq!(store.len().unwrap(), 2);
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(common::log_records(dir.path()), ["Set a=1", "Set b=2", "Remove b", "Set c=3"]);
+}
+
+#[test]
+fn reopen_ranks_keys_by_log_position() {
+    let dir = TempDir::new().unwrap();
+    let mut store = open(&dir, 2);
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    drop(store);
+
+    let mut store = open(&dir, 2);
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    assert_eq!(store.get("a").unwrap(), None);
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("2"));
+}
+
+#[test]
+fn key_past_the_cap_evicts_the_first_inserted() {
+    let dir = TempDir::new().unwrap();
+    let mut store = open(&dir, 3);
+    for key in ["a", "b", "c"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+    // Overwriting a key at the cap doesn't evict, but counts as use.
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    assert_eq!(store.len().unwrap(), 3);
+
+    store.set("d".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.len().unwrap(), 3);
+    assert_eq!(store.get("a").unwrap(), None);
+    store.set("e".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.get("c").unwrap(), None);
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("2"));
+}
+
+#[test]
+fn set_refused_by_backpressure_evicts_nothing() {
+    let dir = TempDir::new().unwrap();
+    // A threshold out of reach keeps the manager's worker from relieving the ceiling.
+    let options = KvStoreOptions::new()
+        .max_keys(3)
+        .uncompacted_ceiling(100)
+        .compaction_threshold(u64::MAX);
+    let manager = StoreManager::open_with_options(dir.path(), options).unwrap();
+    let store = manager.store("capped").unwrap();
+    let mut store = store.write().unwrap();
+    for key in ["a", "b", "c"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+    store.set("a".to_string(), "x".repeat(100)).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    let log = common::log_lines(&dir.path().join("capped"));
+    let metrics = store.metrics();
+
+    assert!(matches!(
+        store.set("d".to_string(), "1".to_string()),
+        Err(KvError::Backpressure { ceiling: 100, .. })
+    ));
+    assert_eq!(store.metrics().since(&metrics), Default::default());
+    assert_eq!(common::log_lines(&dir.path().join("capped")), log);
+    assert_eq!(store.len().unwrap(), 3);
+    for key in ["a", "b", "c"] {
+        assert!(store.get(key).unwrap().is_some(), "{}", key);
+    }
+    assert_eq!(store.get("d").unwrap(), None);
+}
@@ -0,0 +1,87 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use kvstore::{Clock, KvStore, KvStoreOptions, MockClock};
+use tempfile::TempDir;
+
+#[test]
+fn delta_counts_operations_between_samples() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("warmup".to_string(), "0".to_string()).unwrap();
+    store.get("warmup").unwrap();
+    let before = store.metrics();
+
+    for i in 0..10 {
+        store.set(format!("key{}", i), i.to_string()).unwrap();
+    }
+    for i in 0..7 {
+        store.get(&format!("key{}", i)).unwrap();
+    }
+    store.get("missing").unwrap();
+    for i in 0..3 {
+        store.remove(format!("key{}", i)).unwrap();
+    }
+    store.compact().unwrap();
+    let after = store.metrics();
+
+    let delta = after.since(&before);
+    assert_eq!(delta.sets(), 10);
+    assert_eq!(delta.gets(), 8);
+    assert_eq!(delta.removes(), 3);
+    assert_eq!(delta.compactions(), 1);
+
+    // A sample compared with itself, or with a later one, is all zeros.
+    assert_eq!(after.since(&after), Default::default());
+    assert_eq!(before.since(&after), Default::default());
+}
+
+#[test]
+fn compactions_are_counted_and_timed_by_the_clock() {
+    let dir = TempDir::new().unwrap();
+    let clock = MockClock::new(SystemTime::now());
+    let options = KvStoreOptions::new().clock(clock.clone()).compaction_threshold(1);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    assert_eq!(store.metrics().compactions(), 0);
+    assert_eq!(store.metrics().last_compaction(), None);
+    assert_eq!(store.time_since_last_compaction(), None);
+
+    let mut compactions = 0;
+    for round in 0..3 {
+        // Enough stale data to pass a quarter of the log.
+        for i in 0..10 {
+            store.set("key".to_string(), format!("{}-{}", round, i)).unwrap();
+        }
+        assert!(store.metrics().compactions() > compactions);
+        compactions = store.metrics().compactions();
+        assert_eq!(store.metrics().last_compaction(), Some(clock.now()));
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(store.time_since_last_compaction(), Some(Duration::from_secs(60)));
+    }
+    store.compact().unwrap();
+    assert_eq!(store.metrics().compactions(), compactions + 1);
+}
+
+#[test]
+fn metrics_json_has_the_counts_after_known_operations() {
+    let dir = TempDir::new().unwrap();
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().clock(clock.clone())).unwrap();
+    assert_eq!(
+        store.metrics_json(),
+        r#"{"gets":0,"sets":0,"removes":0,"compactions":0,"last_compaction_ms":null}"#
+    );
+
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.get("a").unwrap();
+    store.get("missing").unwrap();
+    store.get("b").unwrap();
+    store.remove("a".to_string()).unwrap();
+    store.compact().unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&store.metrics_json()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"gets": 3, "sets": 2, "removes": 1, "compactions": 1, "last_compaction_ms": 1_700_000_000_000u64})
+    );
+}
@@ -0,0 +1,206 @@
+use kvstore::{KvError, KvStore, KvStoreOptions, MockClock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+use tempfile::TempDir;
+
+const SEEDS: u64 = 200;
+const OPS_PER_SEED: usize = 300;
+/// Few enough keys that overwrites and removes of live keys are common.
+const KEYS: usize = 16;
+/// How long tombstones survive compaction on the seeds that keep them.
+const GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+enum Op {
+    Set(String, String),
+    Get(String),
+    Remove(String),
+    SetMany(Vec<(String, String)>),
+    RemoveAll(Vec<String>),
+    /// Applied atomically: `None` removes the key.
+    Txn(Vec<(String, Option<String>)>),
+    Compact,
+    Reopen,
+    /// Moves the clock on, expiring tombstones older than `GRACE`.
+    Advance(Duration),
+}
+
+fn random_key(rng: &mut StdRng) -> String {
+    format!("key{}", rng.gen_range(0..KEYS))
+}
+
+fn random_value(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..40);
+    (0..len).map(|_| rng.gen_range(b'a'..=b'z') as char).collect()
+}
+
+fn random_op(rng: &mut StdRng) -> Op {
+    match rng.gen_range(0..100) {
+        0..=39 => Op::Set(random_key(rng), random_value(rng)),
+        40..=54 => Op::Get(random_key(rng)),
+        55..=59 => Op::Advance(Duration::from_secs(rng.gen_range(0..90))),
+        60..=74 => Op::Remove(random_key(rng)),
+        75..=79 => Op::SetMany((0..rng.gen_range(1..4)).map(|_| (random_key(rng), random_value(rng))).collect()),
+        80..=82 => Op::RemoveAll((0..rng.gen_range(1..5)).map(|_| random_key(rng)).collect()),
+        83..=92 => Op::Txn(
+            (0..rng.gen_range(1..4))
+                .map(|_| (random_key(rng), rng.gen_bool(0.7).then(|| random_value(rng))))
+                .collect(),
+        ),
+        93..=96 => Op::Compact,
+        _ => Op::Reopen,
+    }
+}
+
+/// Small threshold so writes also trigger compaction on their own. Half
+/// the seeds keep tombstones for `GRACE`, timed by `clock`.
+fn options(seed: u64, clock: &MockClock) -> KvStoreOptions {
+    let options = KvStoreOptions::new()
+        .clock(clock.clone())
+        .compaction_threshold(512)
+        .bloom_filter(seed.is_multiple_of(2));
+    match seed % 4 < 2 {
+        true => options.tombstone_grace(GRACE),
+        false => options,
+    }
+}
+
+/// Applies `op` to the store and the model, checking that any result the
+/// operation returns agrees with the model.
+fn apply(
+    store: &mut KvStore,
+    dir: &TempDir,
+    options: &KvStoreOptions,
+    clock: &MockClock,
+    model: &mut HashMap<String, String>,
+    op: &Op,
+) -> kvstore::Result<()> {
+    match op {
+        Op::Set(key, val) => {
+            store.set(key.clone(), val.clone())?;
+            model.insert(key.clone(), val.clone());
+        }
+        Op::Get(key) => {
+            assert_eq!(store.get(key)?.as_ref(), model.get(key), "get of {}", key);
+        }
+        Op::Remove(key) => match store.remove(key.clone()) {
+            Ok(()) => assert!(model.remove(key).is_some(), "removed absent key {}", key),
+            Err(KvError::KeyNotFound) => assert!(!model.contains_key(key), "failed to remove {}", key),
+            Err(e) => return Err(e),
+        },
+        Op::SetMany(pairs) => {
+            store.set_many(pairs.clone())?;
+            model.extend(pairs.iter().cloned());
+        }
+        Op::RemoveAll(keys) => {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let removed = store.remove_all(&keys)?;
+            let expected = keys.iter().filter(|key| model.remove(**key).is_some()).count();
+            assert_eq!(removed, expected, "remove_all count");
+        }
+        Op::Txn(writes) => {
+            // Removing a key absent at that point fails the whole transaction.
+            let mut staged = model.clone();
+            let mut valid = true;
+            let mut txn = store.transaction();
+            for (key, val) in writes {
+                match val {
+                    Some(val) => {
+                        txn.set(key.clone(), val.clone());
+                        staged.insert(key.clone(), val.clone());
+                    }
+                    None => {
+                        txn.remove(key.clone());
+                        valid &= staged.remove(key).is_some();
+                    }
+                }
+            }
+            match txn.commit() {
+                Ok(()) => {
+                    assert!(valid, "transaction removing an absent key committed");
+                    *model = staged;
+                }
+                Err(KvError::KeyNotFound) => assert!(!valid, "valid transaction rejected"),
+                Err(e) => return Err(e),
+            }
+        }
+        Op::Compact => {
+            store.compact()?;
+            store.debug_verify()?;
+        }
+        Op::Reopen => {
+            *store = KvStore::open_with_options(dir.path(), options.clone())?;
+            store.debug_verify()?;
+        }
+        Op::Advance(by) => clock.advance(*by),
+    }
+    Ok(())
+}
+
+/// The whole keyspace must read back exactly as the model holds it, and the
+/// store's count of stale bytes must match what a reopen recounts from the log.
+fn check(store: &KvStore, dir: &TempDir, options: &KvStoreOptions, model: &HashMap<String, String>) -> kvstore::Result<()> {
+    assert_eq!(store.len()?, model.len(), "key count");
+    for i in 0..KEYS {
+        let key = format!("key{}", i);
+        assert_eq!(store.get(&key)?.as_ref(), model.get(&key), "value of {}", key);
+    }
+    let reopened = KvStore::open_with_options(dir.path(), options.clone())?;
+    assert_eq!(store.uncompacted_bytes(), reopened.uncompacted_bytes(), "uncompacted bytes");
+    Ok(())
+}
+
+fn run_seed(seed: u64) -> kvstore::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let dir = TempDir::new()?;
+    let clock = MockClock::new(SystemTime::now());
+    let options = options(seed, &clock);
+    let mut store = KvStore::open_with_options(dir.path(), options.clone())?;
+    let mut model = HashMap::new();
+    let mut history = Vec::with_capacity(OPS_PER_SEED);
+
+    for _ in 0..OPS_PER_SEED {
+        let op = random_op(&mut rng);
+        history.push(op.clone());
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| -> kvstore::Result<()> {
+            apply(&mut store, &dir, &options, &clock, &mut model, &op)?;
+            check(&store, &dir, &options, &model)
+        }));
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                eprintln!("seed {} failed after {:#?}", seed, history);
+                return Err(e);
+            }
+            Err(panic) => {
+                eprintln!("seed {} diverged from the model after {:#?}", seed, history);
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs random sequences of operations against a store and a `HashMap`
+/// model, checking after every operation that they agree, through automatic
+/// and forced compactions and reopens. A divergence prints the seed and the
+/// operations that led to it; set `KVSTORE_SEED` to replay just that sequence.
+#[test]
+fn store_matches_model() -> kvstore::Result<()> {
+    let seeds = match std::env::var("KVSTORE_SEED") {
+        Ok(seed) => {
+            let seed = seed.parse().expect("KVSTORE_SEED must be a number");
+            seed..seed + 1
+        }
+        Err(_) => 0..SEEDS,
+    };
+
+    for seed in seeds {
+        run_seed(seed)?;
+    }
+    Ok(())
+}
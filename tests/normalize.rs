@@ -0,0 +1,54 @@
+mod common;
+
+use common::log_records;
+use kvstore::{CaseFoldNormalizer, KvError, KvStore, KvStoreOptions, TrimNormalizer};
+use tempfile::TempDir;
+
+#[test]
+fn case_fold_matches_keys_in_any_case() {
+    let dir = TempDir::new().unwrap();
+    let options = || KvStoreOptions::new().key_normalizer(CaseFoldNormalizer);
+    let mut store = KvStore::open_with_options(dir.path(), options()).unwrap();
+
+    store.set("Foo".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.get("foo").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("FOO").unwrap().as_deref(), Some("1"));
+    store.set("fOO".to_string(), "2".to_string()).unwrap();
+    assert_eq!(store.len().unwrap(), 1);
+    store.remove("FoO".to_string()).unwrap();
+    assert_eq!(store.get("foo").unwrap(), None);
+    store.set("Bar".to_string(), "3".to_string()).unwrap();
+    drop(store);
+
+    assert_eq!(log_records(dir.path()), ["Set foo=1", "Set foo=2", "Remove foo", "Set bar=3"]);
+    let store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    assert_eq!(store.get("BAR").unwrap().as_deref(), Some("3"));
+}
+
+#[test]
+fn trim_ignores_surrounding_whitespace() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().key_normalizer(TrimNormalizer)).unwrap();
+
+    store.set("foo ".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.get(" foo").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("foo").unwrap().as_deref(), Some("1"));
+    assert!(matches!(store.set("   ".to_string(), "1".to_string()), Err(KvError::InvalidKey(_))));
+    store.remove("\tfoo\n".to_string()).unwrap();
+    assert_eq!(store.len().unwrap(), 0);
+}
+
+#[test]
+fn keys_logged_before_normalizing_are_normalized_on_replay() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("Key".to_string(), "old".to_string()).unwrap();
+    store.set("KEY".to_string(), "new".to_string()).unwrap();
+    drop(store);
+
+    // The last write wins among keys that now collide.
+    let store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().key_normalizer(CaseFoldNormalizer)).unwrap();
+    assert_eq!(store.len().unwrap(), 1);
+    assert_eq!(store.get("key").unwrap().as_deref(), Some("new"));
+    store.debug_verify().unwrap();
+}
@@ -0,0 +1,82 @@
+use kvstore::{CaseFoldNormalizer, KvError, KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+#[test]
+fn open_existing_refuses_missing_store() {
+    let dir = TempDir::new().unwrap();
+    let missing = dir.path().join("typo");
+
+    assert!(matches!(KvStore::open_existing(&missing), Err(KvError::InvalidDataDir(path)) if path == missing));
+    assert!(!missing.exists());
+
+    // A directory without a log is no store either.
+    assert!(matches!(KvStore::open_existing(dir.path()), Err(KvError::InvalidDataDir(_))));
+    assert!(!dir.path().join("store.log").exists());
+}
+
+#[test]
+fn open_existing_opens_store() {
+    let dir = TempDir::new().unwrap();
+    KvStore::open(dir.path()).unwrap().set("a".to_string(), "1".to_string()).unwrap();
+
+    let store = KvStore::open_existing(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+}
+
+#[test]
+fn open_existing_with_options_applies_them() {
+    let dir = TempDir::new().unwrap();
+    let options = || KvStoreOptions::new().key_normalizer(CaseFoldNormalizer);
+    KvStore::open_with_options(dir.path(), options()).unwrap().set("Key".to_string(), "1".to_string()).unwrap();
+
+    let store = KvStore::open_existing_with_options(dir.path(), options()).unwrap();
+    assert_eq!(store.get("KEY").unwrap().as_deref(), Some("1"));
+    assert!(matches!(
+        KvStore::open_existing_with_options(dir.path().join("typo"), options()),
+        Err(KvError::InvalidDataDir(_))
+    ));
+}
+
+#[test]
+fn empty_log_opens_as_a_new_store() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("store.log"), b"").unwrap();
+
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.len().unwrap(), 0);
+    assert!(store.last_recovery().is_none());
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    drop(store);
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    store.debug_verify().unwrap();
+}
+
+#[test]
+fn open_without_index_reads_without_replaying() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..100 {
+        store.set(format!("key{}", i), i.to_string()).unwrap();
+    }
+    store.set("key7".to_string(), "seven".to_string()).unwrap();
+    store.remove("key8".to_string()).unwrap();
+    drop(store);
+
+    let mut store = KvStore::open_without_index(dir.path()).unwrap();
+    assert!(store.last_recovery().is_none());
+    assert_eq!((store.index_memory_bytes(), store.uncompacted_bytes()), (0, 0));
+    assert_eq!(store.get("key7").unwrap().as_deref(), Some("seven"));
+    assert_eq!(store.get("key8").unwrap(), None);
+    assert_eq!(store.get("key99").unwrap().as_deref(), Some("99"));
+    assert_eq!(store.len().unwrap(), 99);
+
+    // The first write indexes the store as `open` would have.
+    store.set("new".to_string(), "1".to_string()).unwrap();
+    assert!(store.last_recovery().is_some());
+    assert!(store.index_memory_bytes() > 0 && store.uncompacted_bytes() > 0);
+    assert_eq!(store.len().unwrap(), 100);
+    store.debug_verify().unwrap();
+}
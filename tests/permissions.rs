@@ -0,0 +1,39 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use kvstore::{KvStore, KvStoreOptions};
+use tempfile::TempDir;
+
+fn mode(path: &std::path::Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn created_files_get_the_requested_mode() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().file_mode(0o600)).unwrap();
+    store.set("secret".to_string(), "1".to_string()).unwrap();
+    store.set("secret".to_string(), "2".to_string()).unwrap();
+    assert_eq!(mode(&dir.path().join("store.log")), 0o600);
+
+    // Compaction replaces the log; the new one and the side files get the mode too.
+    store.compact().unwrap();
+    store.set_compaction_threshold(4096);
+    for name in ["store.log", "store.hint", "store.compacted", "store.meta"] {
+        assert_eq!(mode(&dir.path().join(name)), 0o600, "{}", name);
+    }
+}
+
+#[test]
+fn existing_log_keeps_its_mode() {
+    let dir = TempDir::new().unwrap();
+    KvStore::open(dir.path()).unwrap().set("a".to_string(), "1".to_string()).unwrap();
+    let log = dir.path().join("store.log");
+    fs::set_permissions(&log, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().file_mode(0o600)).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    assert_eq!(mode(&log), 0o644);
+}
@@ -0,0 +1,98 @@
+use std::fs;
+
+use kvstore::{CaseFoldNormalizer, KvStore, KvStoreOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tempfile::TempDir;
+
+#[test]
+fn totally_corrupt_log_is_set_aside() {
+    let dir = TempDir::new().unwrap();
+    let garbage: Vec<u8> = StdRng::seed_from_u64(7).sample_iter(rand::distributions::Standard).take(4096).collect();
+    fs::write(dir.path().join("store.log"), &garbage).unwrap();
+    fs::write(dir.path().join("store.hint"), b"stale").unwrap();
+    assert!(KvStore::open(dir.path()).is_err());
+
+    let (mut store, aside) = KvStore::open_or_recover(dir.path()).unwrap();
+    let aside = aside.expect("log set aside");
+    assert_eq!(aside.path().parent(), Some(dir.path()));
+    assert!(aside.path().file_name().unwrap().to_str().unwrap().starts_with("store.log.corrupt."));
+    assert_eq!(fs::read(aside.path()).unwrap(), garbage);
+    assert!(!dir.path().join("store.hint").exists());
+
+    assert_eq!(store.len().unwrap(), 0);
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    drop(store);
+    assert_eq!(KvStore::open(dir.path()).unwrap().get("a").unwrap().as_deref(), Some("1"));
+}
+
+#[test]
+fn log_with_no_readable_record_is_set_aside() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("store.log"), b"not json\nnor this").unwrap();
+
+    let (store, aside) = KvStore::open_or_recover(dir.path()).unwrap();
+    let aside = aside.expect("log set aside");
+    assert_eq!(aside.reason(), "none of its 2 records could be read");
+    // Kept whole, including the torn last line `open` would have truncated.
+    assert_eq!(fs::read(aside.path()).unwrap(), b"not json\nnor this");
+    assert_eq!(store.len().unwrap(), 0);
+}
+
+#[test]
+fn log_that_cannot_be_opened_is_set_aside() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("store.log")).unwrap();
+
+    let (store, aside) = KvStore::open_or_recover(dir.path()).unwrap();
+    assert!(aside.expect("log set aside").path().is_dir());
+    assert_eq!(store.len().unwrap(), 0);
+}
+
+#[test]
+fn partly_damaged_log_recovers_like_open() {
+    let dir = TempDir::new().unwrap();
+    KvStore::open(dir.path()).unwrap().set("a".to_string(), "1".to_string()).unwrap();
+    let log = dir.path().join("store.log");
+    let mut bytes = fs::read(&log).unwrap();
+    bytes.extend_from_slice(b"junk\n{\"Set\":");
+    fs::write(&log, bytes).unwrap();
+
+    let (store, aside) = KvStore::open_or_recover(dir.path()).unwrap();
+    assert!(aside.is_none());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    let report = store.last_recovery().unwrap();
+    assert_eq!(report.skipped_offsets().len(), 2);
+    assert!(report.truncated_at().is_some());
+}
+
+#[test]
+fn healthy_and_new_stores_open_normally() {
+    let dir = TempDir::new().unwrap();
+    let (mut store, aside) = KvStore::open_or_recover(dir.path().join("new")).unwrap();
+    assert!(aside.is_none());
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    drop(store);
+
+    let (store, aside) = KvStore::open_or_recover(dir.path().join("new")).unwrap();
+    assert!(aside.is_none());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    assert!(store.last_recovery().unwrap().is_clean());
+}
+
+#[test]
+fn options_apply_to_recovered_and_fresh_stores() {
+    let options = || KvStoreOptions::new().key_normalizer(CaseFoldNormalizer).bloom_filter(true);
+
+    let dir = TempDir::new().unwrap();
+    KvStore::open_with_options(dir.path(), options()).unwrap().set("Key".to_string(), "1".to_string()).unwrap();
+    let (store, aside) = KvStore::open_or_recover_with_options(dir.path(), options()).unwrap();
+    assert!(aside.is_none());
+    assert_eq!(store.get("KEY").unwrap().as_deref(), Some("1"));
+
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("store.log"), b"garbage\n").unwrap();
+    let (mut store, aside) = KvStore::open_or_recover_with_options(dir.path(), options()).unwrap();
+    assert!(aside.is_some());
+    store.set("Other".to_string(), "2".to_string()).unwrap();
+    assert_eq!(store.get("OTHER").unwrap().as_deref(), Some("2"));
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use kvstore::KvStore;
+use tempfile::TempDir;
+
+/// Every entry of `store`, fetched `limit` at a time, and the number of pages.
+fn all_pages(store: &KvStore, limit: usize) -> (Vec<(String, String)>, usize) {
+    let mut entries = Vec::new();
+    let mut cursor = None;
+    let mut pages = 0;
+    loop {
+        let (page, next) = store.scan(cursor.as_deref(), limit).unwrap();
+        assert!(page.len() <= limit.max(1));
+        entries.extend(page);
+        pages += 1;
+        match next {
+            Some(next) => cursor = Some(next),
+            None => return (entries, pages),
+        }
+    }
+}
+
+#[test]
+fn pages_cover_every_key_once_in_order() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in (0..53).rev() {
+        store.set(format!("key{:02}", i), i.to_string()).unwrap();
+    }
+    store.remove("key10".to_string()).unwrap();
+
+    for limit in [1, 7, 13, 52, 100] {
+        let (entries, pages) = all_pages(&store, limit);
+        assert_eq!(entries.len(), 52, "limit {}", limit);
+        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0), "limit {}", limit);
+        assert_eq!(entries.iter().map(|(key, _)| key).collect::<HashSet<_>>().len(), 52);
+        assert!(!entries.iter().any(|(key, _)| key == "key10"));
+        assert_eq!(pages, 52usize.div_ceil(limit), "limit {}", limit);
+    }
+    assert_eq!(all_pages(&store, 0).0.len(), 52);
+
+    drop(store);
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    assert_eq!(all_pages(&store, 10).0.len(), 52);
+}
+
+#[test]
+fn keys_written_between_pages_appear_only_after_the_cursor() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["b", "d", "f"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+    let (page, next) = store.scan(None, 5).unwrap();
+    assert_eq!((page.len(), next), (3, None));
+
+    let (page, next) = store.scan(None, 2).unwrap();
+    assert_eq!(page.len(), 2);
+    assert_eq!(next.as_deref(), Some("d"));
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    store.set("e".to_string(), "2".to_string()).unwrap();
+
+    let (page, next) = store.scan(next.as_deref(), 2).unwrap();
+    let keys: Vec<&str> = page.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!((keys, next), (vec!["e", "f"], None));
+
+    let empty = TempDir::new().unwrap();
+    assert_eq!(KvStore::open(empty.path()).unwrap().scan(None, 10).unwrap(), (vec![], None));
+}
+
+fn glob_keys(store: &KvStore, pattern: &str) -> Vec<String> {
+    store.scan_glob(pattern).unwrap().into_iter().map(|(key, _)| key).collect()
+}
+
+#[test]
+fn scan_glob_matches_wildcards_and_literals() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["user:1:email", "user:1:name", "user:22:email", "user:email", "admin:1:email", "a*b"] {
+        store.set(key.to_string(), key.len().to_string()).unwrap();
+    }
+
+    assert_eq!(glob_keys(&store, "user:*:email"), ["user:1:email", "user:22:email"]);
+    assert_eq!(glob_keys(&store, "user:?:*"), ["user:1:email", "user:1:name"]);
+    assert_eq!(glob_keys(&store, "*:email"), ["admin:1:email", "user:1:email", "user:22:email", "user:email"]);
+    assert_eq!(glob_keys(&store, "*"), store.scan(None, 100).unwrap().0.into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+    assert_eq!(store.scan_glob("user:1:name").unwrap(), [("user:1:name".to_string(), "11".to_string())]);
+    // `*` matches itself too; there's no escaping.
+    assert_eq!(glob_keys(&store, "a*b"), ["a*b"]);
+
+    assert!(glob_keys(&store, "user:???:email").is_empty());
+    assert!(glob_keys(&store, "nobody:*").is_empty());
+    assert!(glob_keys(&store, "").is_empty());
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use std::{
+    fs::File,
+    io,
+    sync::{Arc, Condvar, Mutex, PoisonError, RwLock},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use common::log_records;
+use kvstore::{
+    CompactionScheduler, KvError, KvStore, KvStoreOptions, MockClock, OsStorage, Storage, StoreManager, SyncMethod,
+    SyncPolicy,
+};
+use tempfile::TempDir;
+
+/// Holds a background compaction mid-build. Under `SyncPolicy::Always` the
+/// build syncs the compacted log with `SyncMethod::All` before returning to
+/// the store's lock; once armed, the first such sync waits for `release`.
+/// Writes sync with `SyncMethod::Data` and pass straight through.
+#[derive(Debug, Clone, Default)]
+struct Gate(Arc<(Mutex<GateState>, Condvar)>);
+
+#[derive(Debug, Default)]
+struct GateState {
+    armed: bool,
+    entered: bool,
+    released: bool,
+}
+
+impl Gate {
+    fn arm(&self) {
+        *self.0 .0.lock().unwrap() = GateState { armed: true, ..GateState::default() };
+    }
+
+    /// Waits until a build is held at the gate.
+    fn wait_entered(&self) {
+        let (state, changed) = &*self.0;
+        let (state, timeout) = changed
+            .wait_timeout_while(state.lock().unwrap(), Duration::from_secs(10), |state| !state.entered)
+            .unwrap();
+        assert!(!timeout.timed_out() || state.entered, "no compaction reached the gate");
+    }
+
+    fn release(&self) {
+        self.0 .0.lock().unwrap().released = true;
+        self.0 .1.notify_all();
+    }
+}
+
+impl Storage for Gate {
+    fn sync(&self, file: &File, method: SyncMethod) -> io::Result<()> {
+        if method == SyncMethod::All {
+            let (state, changed) = &*self.0;
+            let mut state = state.lock().unwrap();
+            if state.armed {
+                state.armed = false;
+                state.entered = true;
+                changed.notify_all();
+                let _released = changed.wait_while(state, |state| !state.released).unwrap();
+            }
+        }
+        OsStorage.sync(file, method)
+    }
+}
+
+fn gated(dir: &TempDir, gate: &Gate, options: KvStoreOptions) -> Arc<RwLock<KvStore>> {
+    let options = options.storage(gate.clone()).sync_policy(SyncPolicy::Always).compaction_threshold(1);
+    Arc::new(RwLock::new(KvStore::open_with_options(dir.path(), options).unwrap()))
+}
+
+fn set(store: &RwLock<KvStore>, key: &str, val: &str) {
+    store.write().unwrap_or_else(PoisonError::into_inner).set(key.to_string(), val.to_string()).unwrap();
+}
+
+fn get(store: &RwLock<KvStore>, key: &str) -> Option<String> {
+    store.read().unwrap_or_else(PoisonError::into_inner).get(key).unwrap()
+}
+
+/// A scheduler that only compacts when asked, through `compact_due`.
+fn on_demand(store: &Arc<RwLock<KvStore>>) -> CompactionScheduler {
+    CompactionScheduler::start(Arc::clone(store), Duration::from_secs(3600))
+}
+
+#[test]
+fn writes_during_a_build_survive_install_and_reopen() {
+    let dir = TempDir::new().unwrap();
+    let gate = Gate::default();
+    let store = gated(&dir, &gate, KvStoreOptions::new());
+    let scheduler = on_demand(&store);
+    for i in 0..10 {
+        set(&store, "a", &i.to_string());
+    }
+    set(&store, "b", "1");
+
+    gate.arm();
+    thread::scope(|scope| {
+        let compaction = scope.spawn(|| scheduler.compact_due());
+        gate.wait_entered();
+
+        // The build holds no lock, so writes go through while it waits.
+        let started = Instant::now();
+        set(&store, "a", "during");
+        set(&store, "c", "new");
+        assert_eq!(get(&store, "a").as_deref(), Some("during"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(dir.path().join("store.log.compact.bg").exists());
+
+        gate.release();
+        assert!(compaction.join().unwrap().unwrap());
+    });
+
+    assert_eq!(log_records(dir.path()), ["Set a=9", "Set b=1", "Set a=during", "Set c=new"]);
+    assert!(!dir.path().join("store.log.compact.bg").exists());
+    {
+        let store = store.read().unwrap();
+        assert_eq!(store.metrics().compactions(), 1);
+        store.debug_verify().unwrap();
+    }
+    assert_eq!(get(&store, "a").as_deref(), Some("during"));
+    set(&store, "d", "after");
+
+    drop(scheduler);
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("during"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("c").unwrap().as_deref(), Some("new"));
+    assert_eq!(store.get("d").unwrap().as_deref(), Some("after"));
+}
+
+#[test]
+fn compaction_during_a_build_discards_the_build() {
+    let dir = TempDir::new().unwrap();
+    let gate = Gate::default();
+    let store = gated(&dir, &gate, KvStoreOptions::new());
+    let scheduler = on_demand(&store);
+    for i in 0..10 {
+        set(&store, "a", &i.to_string());
+    }
+
+    gate.arm();
+    thread::scope(|scope| {
+        let compaction = scope.spawn(|| scheduler.compact_due());
+        gate.wait_entered();
+        set(&store, "b", "1");
+        store.write().unwrap().compact().unwrap();
+        set(&store, "c", "1");
+
+        gate.release();
+        // The build describes a log that has since been replaced.
+        assert!(matches!(compaction.join().unwrap(), Err(KvError::CompactionFailed(_))));
+    });
+
+    assert!(!dir.path().join("store.log.compact.bg").exists());
+    assert_eq!(log_records(dir.path()), ["Set a=9", "Set b=1", "Set c=1"]);
+    {
+        let store = store.read().unwrap();
+        assert_eq!(store.metrics().compactions(), 1);
+        store.debug_verify().unwrap();
+    }
+
+    // The discarded build doesn't block the next one.
+    for i in 0..10 {
+        set(&store, "c", &i.to_string());
+    }
+    assert!(scheduler.compact_due().unwrap());
+    assert_eq!(log_records(dir.path()), ["Set a=9", "Set b=1", "Set c=9"]);
+}
+
+#[test]
+fn removals_and_grace_expiry_during_a_build_are_applied() {
+    let dir = TempDir::new().unwrap();
+    let gate = Gate::default();
+    let clock = MockClock::new(SystemTime::now());
+    let options = KvStoreOptions::new().clock(clock.clone()).tombstone_grace(Duration::from_secs(3600));
+    let store = gated(&dir, &gate, options);
+    let scheduler = on_demand(&store);
+    for key in ["a", "b", "c", "d"] {
+        set(&store, key, "1");
+    }
+    for i in 0..10 {
+        set(&store, "e", &i.to_string());
+    }
+    store.write().unwrap().remove("a".to_string()).unwrap();
+    clock.advance(Duration::from_secs(7200));
+    store.write().unwrap().remove("b".to_string()).unwrap();
+
+    gate.arm();
+    thread::scope(|scope| {
+        let compaction = scope.spawn(|| scheduler.compact_due());
+        gate.wait_entered();
+        // `b`'s grace runs out and `c` goes while the build copies them.
+        clock.advance(Duration::from_secs(7200));
+        store.write().unwrap().remove("c".to_string()).unwrap();
+        set(&store, "d", "2");
+        gate.release();
+        assert!(compaction.join().unwrap().unwrap());
+    });
+
+    // `a`'s grace had run out when the build was planned; `b`'s hadn't.
+    assert_eq!(log_records(dir.path()), ["Set c=1", "Set d=1", "Set e=9", "Remove b", "Remove c", "Set d=2"]);
+    {
+        let store = store.read().unwrap();
+        for key in ["a", "b", "c"] {
+            assert_eq!(store.get(key).unwrap(), None, "{}", key);
+        }
+        assert_eq!(store.get("d").unwrap().as_deref(), Some("2"));
+        store.debug_verify().unwrap();
+    }
+
+    // The next compaction drops `b`'s expired tombstone and keeps `c`'s.
+    store.write().unwrap().compact().unwrap();
+    assert_eq!(log_records(dir.path()), ["Set d=2", "Set e=9", "Remove c"]);
+    let uncompacted = store.read().unwrap().uncompacted_bytes();
+    drop(scheduler);
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.uncompacted_bytes(), uncompacted);
+    assert_eq!(store.len().unwrap(), 2);
+}
+
+#[test]
+fn manager_compacts_only_stores_over_their_threshold() {
+    let dir = TempDir::new().unwrap();
+    let manager = StoreManager::open_with_options(dir.path(), KvStoreOptions::new().compaction_threshold(2000)).unwrap();
+    let write = |name: &str, key_count: usize, overwrites: usize| {
+        let store = manager.store(name).unwrap();
+        let mut store = store.write().unwrap();
+        for i in 0..key_count {
+            store.set(format!("key{}", i), format!("{:0100}", 0)).unwrap();
+        }
+        for i in 0..overwrites {
+            store.set("key0".to_string(), format!("{:0100}", i + 1)).unwrap();
+        }
+    };
+    // Under the threshold.
+    write("small", 1, 5);
+    // Over it, but less than a quarter of the log is stale.
+    write("sparse", 200, 30);
+    // Over it.
+    write("due", 1, 50);
+
+    let compacted = manager.compact_due().unwrap();
+    assert!(compacted.iter().all(|name| name == "due"), "{:?}", compacted);
+    let metrics = manager.metrics();
+    assert_eq!(metrics["due"].compactions(), 1);
+    assert_eq!(metrics["small"].compactions(), 0);
+    assert_eq!(metrics["sparse"].compactions(), 0);
+    assert!(manager.compact_due().unwrap().is_empty());
+
+    let store = manager.store("due").unwrap();
+    assert_eq!(store.read().unwrap().get("key0").unwrap(), Some(format!("{:0100}", 50)));
+}
+
+#[test]
+fn scheduler_compacts_in_the_background() {
+    let dir = TempDir::new().unwrap();
+    let store = Arc::new(RwLock::new(
+        KvStore::open_with_options(dir.path(), KvStoreOptions::new().compaction_threshold(1)).unwrap(),
+    ));
+    let _scheduler = CompactionScheduler::start(Arc::clone(&store), Duration::from_millis(10));
+    for i in 0..20 {
+        set(&store, "a", &i.to_string());
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while log_records(dir.path()).len() > 1 {
+        assert!(Instant::now() < deadline, "background compaction never ran");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(get(&store, "a").as_deref(), Some("19"));
+}
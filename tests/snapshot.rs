@@ -0,0 +1,46 @@
+use kvstore::KvStore;
+use tempfile::TempDir;
+
+#[test]
+fn snapshot_ignores_later_writes_and_compactions() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("user:1".to_string(), "a".to_string()).unwrap();
+    store.set("user:2".to_string(), "b".to_string()).unwrap();
+    store.set("other".to_string(), "c".to_string()).unwrap();
+
+    let snapshot = store.snapshot().unwrap();
+    store.set("user:1".to_string(), "changed".to_string()).unwrap();
+    store.remove("user:2".to_string()).unwrap();
+    store.set("user:3".to_string(), "new".to_string()).unwrap();
+    store.compact().unwrap();
+
+    assert_eq!(snapshot.len(), 3);
+    assert_eq!(snapshot.get("user:1").unwrap().as_deref(), Some("a"));
+    assert_eq!(snapshot.get("user:2").unwrap().as_deref(), Some("b"));
+    assert_eq!(snapshot.get("user:3").unwrap(), None);
+    assert_eq!(
+        snapshot.scan("user:").unwrap(),
+        [("user:1".to_string(), "a".to_string()), ("user:2".to_string(), "b".to_string())]
+    );
+
+    // The store itself moved on.
+    assert_eq!(store.get("user:1").unwrap().as_deref(), Some("changed"));
+    assert_eq!(store.snapshot().unwrap().scan("user:").unwrap().len(), 2);
+}
+
+#[test]
+fn snapshot_of_an_unindexed_store_replays_the_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.remove("a".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    drop(store);
+
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    let snapshot = store.snapshot().unwrap();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(snapshot.get("b").unwrap().as_deref(), Some("2"));
+    assert!(KvStore::open(TempDir::new().unwrap().path()).unwrap().snapshot().unwrap().is_empty());
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{log_records, FailAfter};
+use kvstore::engine::read_log;
+use kvstore::{KvError, KvStore, KvStoreOptions, OsStorage, Storage, SyncMethod, SyncPolicy};
+use tempfile::TempDir;
+
+/// A storage operation, as recorded by `Recorder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Call {
+    Sync(SyncMethod),
+    SyncDir(PathBuf),
+    Rename(PathBuf, PathBuf),
+}
+
+/// Records syncs and renames before doing them. With `cross_device` set,
+/// renames between directories fail the way they do across filesystems.
+#[derive(Debug, Clone, Default)]
+struct Recorder {
+    calls: Arc<Mutex<Vec<Call>>>,
+    cross_device: bool,
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<Call> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+}
+
+impl Storage for Recorder {
+    fn sync(&self, file: &File, method: SyncMethod) -> io::Result<()> {
+        self.calls.lock().unwrap().push(Call::Sync(method));
+        OsStorage.sync(file, method)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.calls.lock().unwrap().push(Call::SyncDir(path.to_path_buf()));
+        OsStorage.sync_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.calls.lock().unwrap().push(Call::Rename(from.to_path_buf(), to.to_path_buf()));
+        if self.cross_device && from.parent() != to.parent() {
+            return Err(io::Error::from(io::ErrorKind::CrossesDevices));
+        }
+        OsStorage.rename(from, to)
+    }
+}
+
+fn log_len(dir: &TempDir) -> u64 {
+    std::fs::metadata(dir.path().join("store.log")).unwrap().len()
+}
+
+#[test]
+fn failed_write_is_rolled_back() {
+    let dir = TempDir::new().unwrap();
+    let storage = FailAfter::default();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().storage(storage.clone())).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    let len = log_len(&dir);
+
+    storage.limit(5);
+    assert!(matches!(store.set("b".to_string(), "2".to_string()), Err(KvError::Io(_))));
+    assert_eq!(log_len(&dir), len);
+    assert_eq!(store.get("b").unwrap(), None);
+
+    storage.unlimit();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set c=3"]);
+    KvStore::open(dir.path()).unwrap().debug_verify().unwrap();
+}
+
+#[test]
+fn dropped_bytes_are_a_short_write() {
+    let dir = TempDir::new().unwrap();
+    let storage = FailAfter { silent: true, ..FailAfter::default() };
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().storage(storage.clone())).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    let len = log_len(&dir);
+
+    storage.limit(5);
+    match store.set("b".to_string(), "2".to_string()) {
+        Err(KvError::ShortWrite { expected, written }) => {
+            assert_eq!(written, 5);
+            assert!(expected > written);
+        }
+        other => panic!("expected a short write, got {:?}", other),
+    }
+    assert_eq!(log_len(&dir), len);
+
+    storage.unlimit();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set c=3"]);
+}
+
+#[test]
+fn sync_uses_sync_data_by_default() {
+    let dir = TempDir::new().unwrap();
+    let storage = Recorder::default();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().storage(storage.clone())).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    assert_eq!(storage.take(), []);
+
+    store.sync().unwrap();
+    assert_eq!(storage.take(), [Call::Sync(SyncMethod::Data)]);
+}
+
+#[test]
+fn sync_method_picks_the_sync_call() {
+    for method in [SyncMethod::Data, SyncMethod::All] {
+        let dir = TempDir::new().unwrap();
+        let storage = Recorder::default();
+        let options = KvStoreOptions::new().storage(storage.clone()).sync_method(method).sync_policy(SyncPolicy::Always);
+        let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+        storage.take();
+
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        store.sync().unwrap();
+        assert_eq!(storage.take(), [Call::Sync(method), Call::Sync(method)]);
+    }
+}
+
+#[test]
+fn compaction_syncs_the_directory_after_the_rename() {
+    let dir = TempDir::new().unwrap();
+    let storage = Recorder::default();
+    let options = KvStoreOptions::new().storage(storage.clone()).sync_policy(SyncPolicy::Always);
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    storage.take();
+
+    store.compact().unwrap();
+    let log = dir.path().join("store.log");
+    assert_eq!(
+        storage.take(),
+        [
+            Call::Sync(SyncMethod::All),
+            Call::Sync(SyncMethod::All),
+            Call::Rename(dir.path().join("store.log.compact"), log),
+            Call::SyncDir(dir.path().to_path_buf()),
+        ]
+    );
+}
+
+#[test]
+fn compaction_without_sync_policy_skips_syncs() {
+    let dir = TempDir::new().unwrap();
+    let storage = Recorder::default();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().storage(storage.clone())).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+
+    store.compact().unwrap();
+    assert_eq!(storage.take(), [Call::Rename(dir.path().join("store.log.compact"), dir.path().join("store.log"))]);
+}
+
+#[test]
+fn cross_device_compaction_copies_the_log_into_place() {
+    let dir = TempDir::new().unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let storage = Recorder { cross_device: true, ..Recorder::default() };
+    let options = KvStoreOptions::new().storage(storage.clone()).compaction_temp_dir(temp_dir.path());
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    store.set("b".to_string(), "3".to_string()).unwrap();
+
+    store.compact().unwrap();
+    let calls = storage.take();
+    let log = dir.path().join("store.log");
+    let local = dir.path().join("store.log.compact");
+    let [Call::Rename(from, to), Call::Sync(SyncMethod::All), Call::Rename(local_from, local_to)] = &calls[..] else {
+        panic!("unexpected calls {:?}", calls);
+    };
+    assert_eq!(from.parent(), Some(temp_dir.path()));
+    assert_eq!((to, local_from, local_to), (&log, &local, &log));
+
+    assert!(!local.exists());
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    assert_eq!(log_records(dir.path()), ["Set a=2", "Set b=3"]);
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    drop(store);
+    KvStore::open(dir.path()).unwrap().debug_verify().unwrap();
+}
+
+#[test]
+fn flush_interval_syncs_in_the_background_until_drop() {
+    let dir = TempDir::new().unwrap();
+    let storage = Recorder::default();
+    let options = KvStoreOptions::new().storage(storage.clone()).flush_interval(Duration::from_millis(10));
+    let mut store = KvStore::open_with_options(dir.path(), options).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !storage.calls.lock().unwrap().contains(&Call::Sync(SyncMethod::Data)) {
+        assert!(Instant::now() < deadline, "no background sync");
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(KvStore::open(dir.path()).unwrap().get("a").unwrap().as_deref(), Some("1"));
+
+    drop(store);
+    storage.take();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(storage.take(), []);
+}
+
+/// A log that can't be opened for writing, as on a read-only mount.
+#[derive(Debug)]
+struct ReadOnlyMount;
+
+impl Storage for ReadOnlyMount {
+    fn open_log(&self, _path: &Path, _options: &OpenOptions) -> io::Result<File> {
+        Err(io::Error::from(io::ErrorKind::ReadOnlyFilesystem))
+    }
+}
+
+#[test]
+fn read_only_mount_is_reported_as_such() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    drop(store);
+
+    match KvStore::open_with_options(dir.path(), KvStoreOptions::new().storage(ReadOnlyMount)) {
+        Err(KvError::ReadOnlyFilesystem(path)) => assert_eq!(path, dir.path()),
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    // The read-only tools still see the store.
+    assert_eq!(KvStore::quick_stats(dir.path()).unwrap().estimated_keys(), 1);
+    assert_eq!(read_log(&dir.path().join("store.log")).unwrap().count(), 1);
+}
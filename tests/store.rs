@@ -0,0 +1,342 @@
+mod common;
+
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::log_records;
+use kvstore::cmd::Command;
+use kvstore::engine::read_log;
+use kvstore::{KvError, KvStore, KvStoreOptions, LogPointer, MockClock, SetOutcome};
+use tempfile::TempDir;
+
+#[test]
+fn destroy_removes_only_store_files() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    store.compact().unwrap();
+    drop(store);
+    fs::write(dir.path().join("notes.txt"), b"keep me").unwrap();
+
+    KvStore::destroy(dir.path()).unwrap();
+    let left: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(left, ["notes.txt"]);
+    assert_eq!(fs::read(dir.path().join("notes.txt")).unwrap(), b"keep me");
+}
+
+#[test]
+fn destroy_removes_emptied_directory() {
+    let parent = TempDir::new().unwrap();
+    let dir = parent.path().join("store");
+    KvStore::open(&dir).unwrap().set("a".to_string(), "1".to_string()).unwrap();
+
+    KvStore::destroy(&dir).unwrap();
+    assert!(!dir.exists());
+    // Nothing left to remove is not an error.
+    KvStore::destroy(&dir).unwrap();
+}
+
+#[test]
+fn index_memory_grows_linearly_with_keys() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    let empty = store.index_memory_bytes();
+
+    // Each key holds at least its map entry and its 8 key bytes; spare table
+    // capacity may add up to about as much again.
+    let entry = std::mem::size_of::<(String, LogPointer)>() + 8;
+    let mut per_key = Vec::new();
+    for n in [1_000, 4_000, 16_000] {
+        while store.len().unwrap() < n {
+            let i = store.len().unwrap();
+            store.set(format!("key{:05}", i), "v".to_string()).unwrap();
+        }
+        let bytes = (store.index_memory_bytes() - empty) / n;
+        assert!((entry..3 * entry).contains(&bytes), "{} bytes per key at {} keys", bytes, n);
+        per_key.push(bytes);
+    }
+    assert!(per_key.windows(2).all(|w| w[1] * 2 > w[0] && w[0] * 2 > w[1]), "{:?}", per_key);
+}
+
+#[test]
+fn set_tracked_returns_where_the_record_landed() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    let log_path = dir.path().join("store.log");
+
+    let mut end = 0;
+    for (key, val) in [("a", "1"), ("b", "22"), ("a", "333")] {
+        let ptr = store.set_tracked(key.to_string(), val.to_string()).unwrap();
+        let len = fs::metadata(&log_path).unwrap().len();
+        assert_eq!((ptr.offset(), ptr.offset() + ptr.len()), (end, len));
+        end = len;
+    }
+}
+
+#[test]
+fn pointer_resolves_to_the_keys_record() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.set("a".to_string(), "3".to_string()).unwrap();
+    store.set("c".to_string(), "4".to_string()).unwrap();
+    store.remove("c".to_string()).unwrap();
+    assert_eq!(store.pointer("c").unwrap(), None);
+    assert_eq!(store.pointer("missing").unwrap(), None);
+
+    let records: Vec<(u64, Command)> = read_log(&dir.path().join("store.log")).unwrap().map(Result::unwrap).collect();
+    for (key, val) in [("a", "3"), ("b", "2")] {
+        let ptr = store.pointer(key).unwrap().unwrap();
+        let (_, record) = records.iter().find(|(offset, _)| *offset == ptr.offset()).unwrap();
+        assert!(matches!(record, Command::Set { key: k, val: v, .. } if k == key && v == val), "{:?}", record);
+    }
+}
+
+#[test]
+fn pointer_reports_an_unreadable_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    drop(store);
+
+    // Without an index the pointer comes from scanning the log.
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    assert!(store.pointer("a").unwrap().is_some());
+    fs::remove_file(dir.path().join("store.log")).unwrap();
+    assert!(matches!(store.pointer("a"), Err(KvError::Io(_))));
+}
+
+#[test]
+fn unchanged_values_are_not_rewritten() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert!(store.set_if_changed("a".to_string(), "1".to_string()).unwrap());
+    assert!(!store.set_if_changed("a".to_string(), "1".to_string()).unwrap());
+    assert!(store.set_if_changed("a".to_string(), "2".to_string()).unwrap());
+    // Plain `set` still writes every time unless told otherwise.
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set a=2", "Set a=2"]);
+
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open_with_options(dir.path(), KvStoreOptions::new().skip_unchanged(true)).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("a".to_string(), String::new()).unwrap();
+    store.set("a".to_string(), String::new()).unwrap();
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set a="]);
+}
+
+#[test]
+fn multi_get_returns_only_present_keys() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    store.remove("c".to_string()).unwrap();
+
+    let expected = [("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())].into();
+    assert_eq!(store.multi_get(&["a", "missing", "b", "c", "a"]).unwrap(), expected);
+    assert!(store.multi_get(&[]).unwrap().is_empty());
+    drop(store);
+
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    assert_eq!(store.multi_get(&["b", "c", "a"]).unwrap(), expected);
+}
+
+#[test]
+fn get_with_metadata_returns_the_index_pointer() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    store.set("a".to_string(), "3".to_string()).unwrap();
+
+    for (key, val) in [("a", "3"), ("b", "2")] {
+        let (got, ptr) = store.get_with_metadata(key).unwrap().unwrap();
+        assert_eq!(got, val);
+        assert_eq!(Some(ptr), store.pointer(key).unwrap());
+    }
+    assert_eq!(store.get_with_metadata("missing").unwrap(), None);
+}
+
+#[test]
+fn empty_value_is_kept_apart_from_absent() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("empty".to_string(), String::new()).unwrap();
+    assert_eq!(store.get("empty").unwrap().as_deref(), Some(""));
+    assert_eq!(store.get("missing").unwrap(), None);
+    drop(store);
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("empty").unwrap().as_deref(), Some(""));
+    assert_eq!(store.len().unwrap(), 1);
+}
+
+#[test]
+fn get_many_prefixed_matches_a_per_key_scan() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for i in 0..30 {
+        for user in 0..3 {
+            store.set(format!("user:{}:{:02}", user, i), format!("{}-{}", user, i)).unwrap();
+        }
+    }
+    // Rewrites and removals put matching records out of key order.
+    store.set("user:1:05".to_string(), "new".to_string()).unwrap();
+    store.remove("user:1:10".to_string()).unwrap();
+    store.set("user:10".to_string(), "other".to_string()).unwrap();
+
+    let scanned = store.snapshot().unwrap().scan("user:1:").unwrap();
+    assert_eq!(scanned.len(), 29);
+    assert_eq!(store.get_many_prefixed("user:1:").unwrap(), scanned);
+    assert!(store.get_many_prefixed("nobody:").unwrap().is_empty());
+    drop(store);
+
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    assert_eq!(store.get_many_prefixed("user:1:").unwrap(), scanned);
+}
+
+#[test]
+fn history_lists_every_value_still_in_the_log() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for val in ["1", "2", "3"] {
+        store.set("a".to_string(), val.to_string()).unwrap();
+        store.set("b".to_string(), format!("b{}", val)).unwrap();
+    }
+    assert_eq!(store.history("a").unwrap(), ["1", "2", "3"]);
+    assert!(store.history("missing").unwrap().is_empty());
+
+    // A removal doesn't erase history, but compaction does.
+    store.remove("a".to_string()).unwrap();
+    store.set("a".to_string(), "4".to_string()).unwrap();
+    assert_eq!(store.history("a").unwrap(), ["1", "2", "3", "4"]);
+    store.compact().unwrap();
+    assert_eq!(store.history("a").unwrap(), ["4"]);
+}
+
+#[test]
+fn get_with_age_counts_from_the_write_by_the_clock() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("store.log"), "{\"Set\":{\"key\":\"old\",\"val\":\"0\"}}\n").unwrap();
+    // Write times are kept in whole milliseconds.
+    let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let options = || KvStoreOptions::new().clock(clock.clone());
+    let mut store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.get_with_age("a").unwrap(), Some(("1".to_string(), Some(Duration::ZERO))));
+
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(store.get_with_age("a").unwrap(), Some(("1".to_string(), Some(Duration::from_secs(90)))));
+    // Records written without a timestamp have no age.
+    assert_eq!(store.get_with_age("old").unwrap(), Some(("0".to_string(), None)));
+    assert_eq!(store.get_with_age("missing").unwrap(), None);
+
+    // Rewriting resets the age, and the time survives a reopen.
+    store.set("a".to_string(), "2".to_string()).unwrap();
+    clock.advance(Duration::from_secs(5));
+    drop(store);
+    let store = KvStore::open_with_options(dir.path(), options()).unwrap();
+    assert_eq!(store.get_with_age("a").unwrap(), Some(("2".to_string(), Some(Duration::from_secs(5)))));
+}
+
+#[test]
+fn remove_all_skips_absent_keys() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    for key in ["a", "b", "c", "d"] {
+        store.set(key.to_string(), "1".to_string()).unwrap();
+    }
+
+    assert_eq!(store.remove_all(&["a", "missing", "c", "a"]).unwrap(), 2);
+    assert_eq!(store.remove_all(&["a", "missing"]).unwrap(), 0);
+    assert_eq!(store.remove_all(&[]).unwrap(), 0);
+    assert_eq!(store.get("a").unwrap(), None);
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("c").unwrap(), None);
+    assert_eq!(store.len().unwrap(), 2);
+    drop(store);
+
+    assert_eq!(log_records(dir.path())[4..], ["Remove a", "Remove c"]);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.len().unwrap(), 2);
+    store.debug_verify().unwrap();
+}
+
+#[test]
+fn values_carry_their_schema_version() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set_versioned("user:1".to_string(), "{\"name\":\"a\"}".to_string(), 1).unwrap();
+    store.set_versioned("user:2".to_string(), "{\"first\":\"b\"}".to_string(), 2).unwrap();
+    store.set("plain".to_string(), "x".to_string()).unwrap();
+
+    assert_eq!(store.get_versioned("user:1").unwrap(), Some(("{\"name\":\"a\"}".to_string(), 1)));
+    assert_eq!(store.get_versioned("user:2").unwrap(), Some(("{\"first\":\"b\"}".to_string(), 2)));
+    assert_eq!(store.get_versioned("plain").unwrap(), Some(("x".to_string(), 0)));
+    assert_eq!(store.get_versioned("missing").unwrap(), None);
+
+    // Versions survive compaction and reopen, and a plain set drops the tag.
+    store.set_versioned("user:1".to_string(), "{\"first\":\"a\"}".to_string(), 2).unwrap();
+    store.set("user:2".to_string(), "raw".to_string()).unwrap();
+    store.compact().unwrap();
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get_versioned("user:1").unwrap(), Some(("{\"first\":\"a\"}".to_string(), 2)));
+    assert_eq!(store.get_versioned("user:2").unwrap(), Some(("raw".to_string(), 0)));
+}
+
+#[test]
+fn replace_only_writes_existing_keys() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+
+    store.replace("a", "2".to_string()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    assert!(matches!(store.replace("missing", "1".to_string()), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get("missing").unwrap(), None);
+
+    // A removed key is absent too.
+    store.remove("a".to_string()).unwrap();
+    assert!(matches!(store.replace("a", "3".to_string()), Err(KvError::KeyNotFound)));
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set a=2", "Remove a"]);
+}
+
+#[test]
+fn set_report_tells_created_updated_and_unchanged_apart() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.set_report("a".to_string(), "1".to_string()).unwrap(), SetOutcome::Created);
+    assert_eq!(store.set_report("a".to_string(), "2".to_string()).unwrap(), SetOutcome::Updated);
+    assert_eq!(store.set_report("a".to_string(), "2".to_string()).unwrap(), SetOutcome::Unchanged);
+    store.remove("a".to_string()).unwrap();
+    assert_eq!(store.set_report("a".to_string(), "2".to_string()).unwrap(), SetOutcome::Created);
+    drop(store);
+    // Without `skip_unchanged`, an unchanged value is still written.
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set a=2", "Set a=2", "Remove a", "Set a=2"]);
+}
+
+#[test]
+fn preload_reads_a_populated_store() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.preload().unwrap();
+    for i in 0..500 {
+        store.set(format!("key{}", i), "v".repeat(100)).unwrap();
+    }
+    store.preload().unwrap();
+    assert_eq!(store.get("key250").unwrap(), Some("v".repeat(100)));
+    drop(store);
+
+    let store = KvStore::open_without_index(dir.path()).unwrap();
+    store.preload().unwrap();
+    assert_eq!(store.len().unwrap(), 500);
+}
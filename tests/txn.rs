@@ -0,0 +1,183 @@
+mod common;
+
+use std::fs;
+
+use common::{log_lines, log_records};
+use kvstore::cmd::Command;
+use kvstore::engine::read_log;
+use kvstore::{KvError, KvStore};
+use tempfile::TempDir;
+
+#[test]
+fn committed_transaction_is_all_visible() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("c".to_string(), "0".to_string()).unwrap();
+
+    let mut txn = store.transaction();
+    txn.set("a".to_string(), "1".to_string());
+    txn.set("b".to_string(), "2".to_string());
+    txn.remove("c".to_string());
+    txn.commit().unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("2"));
+    assert_eq!(store.get("c").unwrap(), None);
+    drop(store);
+
+    assert_eq!(log_records(dir.path()), ["Set c=0", "Begin", "Set a=1", "Set b=2", "Remove c", "Commit 3"]);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("1"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("2"));
+    assert_eq!(store.get("c").unwrap(), None);
+}
+
+#[test]
+fn crash_before_commit_marker_discards_transaction() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("x".to_string(), "0".to_string()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("a".to_string(), "1".to_string());
+    txn.set("x".to_string(), "1".to_string());
+    txn.commit().unwrap();
+    drop(store);
+
+    // Lose the commit marker, as a crash between the writes would.
+    let lines = log_lines(dir.path());
+    fs::write(dir.path().join("store.log"), lines[..lines.len() - 1].concat()).unwrap();
+
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap(), None);
+    assert_eq!(store.get("x").unwrap().as_deref(), Some("0"));
+    let report = store.last_recovery().unwrap();
+    assert_eq!(report.discarded_transactions(), [lines[0].len() as u64]);
+    assert_eq!(report.truncated_at(), Some(lines[0].len() as u64));
+
+    // The discarded records are gone, so a later write can't join them.
+    store.set("b".to_string(), "2".to_string()).unwrap();
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set x=0", "Set b=2"]);
+}
+
+#[test]
+fn failed_commit_writes_nothing() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+
+    let mut txn = store.transaction();
+    txn.set("b".to_string(), "2".to_string());
+    txn.remove("missing".to_string());
+    assert!(matches!(txn.commit(), Err(KvError::KeyNotFound)));
+
+    // Dropped uncommitted, a transaction writes nothing either.
+    let mut txn = store.transaction();
+    txn.set("c".to_string(), "3".to_string());
+    drop(txn);
+
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(store.get("c").unwrap(), None);
+    drop(store);
+    assert_eq!(log_records(dir.path()), ["Set a=1"]);
+}
+
+#[test]
+fn transactions_interleave_with_single_writes() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("b".to_string(), "2".to_string());
+    txn.set("a".to_string(), "3".to_string());
+    txn.commit().unwrap();
+    store.remove("b".to_string()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("b".to_string(), "4".to_string());
+    txn.commit().unwrap();
+    store.set("c".to_string(), "5".to_string()).unwrap();
+    drop(store);
+
+    assert_eq!(
+        log_records(dir.path()),
+        ["Set a=1", "Begin", "Set b=2", "Set a=3", "Commit 2", "Remove b", "Begin", "Set b=4", "Commit 1", "Set c=5"]
+    );
+    let store = KvStore::open(dir.path()).unwrap();
+    assert!(store.last_recovery().unwrap().discarded_transactions().is_empty());
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("3"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("4"));
+    assert_eq!(store.get("c").unwrap().as_deref(), Some("5"));
+    store.debug_verify().unwrap();
+}
+
+#[test]
+fn damaged_transactions_are_discarded_and_single_writes_kept() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("a".to_string(), "1".to_string());
+    txn.set("b".to_string(), "2".to_string());
+    txn.commit().unwrap();
+    store.set("c".to_string(), "3".to_string()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("d".to_string(), "4".to_string());
+    txn.commit().unwrap();
+    store.set("e".to_string(), "5".to_string()).unwrap();
+    drop(store);
+
+    // Lose a record of the first transaction and the commit marker of the
+    // second. The write after it then reads as part of that transaction.
+    let mut lines = log_lines(dir.path());
+    assert_eq!(lines.len(), 9);
+    lines.remove(7);
+    lines.remove(2);
+    fs::write(dir.path().join("store.log"), lines.concat()).unwrap();
+
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap(), None);
+    assert_eq!(store.get("b").unwrap(), None);
+    assert_eq!(store.get("c").unwrap().as_deref(), Some("3"));
+    assert_eq!(store.get("d").unwrap(), None);
+    assert_eq!(store.get("e").unwrap(), None);
+    assert_eq!(store.last_recovery().unwrap().discarded_transactions().len(), 2);
+}
+
+#[test]
+fn swap_exchanges_values_in_one_transaction() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    store.set("b".to_string(), "2".to_string()).unwrap();
+
+    store.swap("a", "b").unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("1"));
+    store.swap("a", "a").unwrap();
+
+    assert!(matches!(store.swap("a", "missing"), Err(KvError::KeyNotFound)));
+    assert!(matches!(store.swap("missing", "b"), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    drop(store);
+
+    assert_eq!(log_records(dir.path()), ["Set a=1", "Set b=2", "Begin", "Set a=2", "Set b=1", "Commit 2"]);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("a").unwrap().as_deref(), Some("2"));
+    assert_eq!(store.get("b").unwrap().as_deref(), Some("1"));
+}
+
+#[test]
+fn markers_are_told_apart_from_keyed_records() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("a".to_string(), "1".to_string()).unwrap();
+    let mut txn = store.transaction();
+    txn.set("b".to_string(), "2".to_string());
+    txn.remove("a".to_string());
+    txn.commit().unwrap();
+    drop(store);
+
+    let records: Vec<Command> = read_log(&dir.path().join("store.log")).unwrap().map(|r| r.unwrap().1).collect();
+    let markers: Vec<bool> = records.iter().map(Command::is_marker).collect();
+    assert_eq!(markers, [false, true, false, false, true]);
+    let keys: Vec<&str> = records.iter().filter(|r| !r.is_marker()).map(Command::key).collect();
+    assert_eq!(keys, ["a", "b", "a"]);
+}
@@ -0,0 +1,92 @@
+use kvstore::{KvError, KvStore};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+    tags: Vec<String>,
+}
+
+#[test]
+fn typed_values_round_trip_as_json() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    let user = User { name: "ada".to_string(), age: 36, tags: vec!["admin".to_string()] };
+    store.set_typed("user".to_string(), &user).unwrap();
+    store.set_typed("scores".to_string(), &vec![3, 1, 2]).unwrap();
+
+    assert_eq!(store.get("scores").unwrap().as_deref(), Some("[3,1,2]"));
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get_typed::<User>("user").unwrap(), Some(user));
+    assert_eq!(store.get_typed::<Vec<u32>>("scores").unwrap(), Some(vec![3, 1, 2]));
+    assert_eq!(store.get_typed::<User>("missing").unwrap(), None);
+}
+
+#[test]
+fn value_of_the_wrong_type_is_a_serde_error() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("plain".to_string(), "not json".to_string()).unwrap();
+    store.set_typed("scores".to_string(), &[1, 2]).unwrap();
+
+    assert!(matches!(store.get_typed::<u32>("plain"), Err(KvError::Serde(_))));
+    assert!(matches!(store.get_typed::<User>("scores"), Err(KvError::Serde(_))));
+}
+
+#[test]
+fn pushes_order_the_list_and_ranges_slice_it() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.rpush("q".to_string(), "b".to_string()).unwrap(), 1);
+    assert_eq!(store.rpush("q".to_string(), "c".to_string()).unwrap(), 2);
+    assert_eq!(store.lpush("q".to_string(), "a".to_string()).unwrap(), 3);
+    store.rpush("q".to_string(), "d".to_string()).unwrap();
+
+    let range = |store: &KvStore, start, stop| store.lrange("q", start, stop).unwrap();
+    assert_eq!(range(&store, 0, -1), ["a", "b", "c", "d"]);
+    assert_eq!(range(&store, 1, 2), ["b", "c"]);
+    assert_eq!(range(&store, -2, -1), ["c", "d"]);
+    assert_eq!(range(&store, -100, 1), ["a", "b"]);
+    assert_eq!(range(&store, 2, 100), ["c", "d"]);
+    assert!(range(&store, 3, 1).is_empty());
+    assert!(range(&store, 10, 20).is_empty());
+    assert!(store.lrange("missing", 0, -1).unwrap().is_empty());
+
+    drop(store);
+    let store = KvStore::open(dir.path()).unwrap();
+    assert_eq!(store.get("q").unwrap().as_deref(), Some("[\"a\",\"b\",\"c\",\"d\"]"));
+    assert_eq!(store.lrange("q", -1, -1).unwrap(), ["d"]);
+}
+
+#[test]
+fn hash_fields_are_set_and_read_together() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    assert!(store.hset("user:1".to_string(), "name".to_string(), "ada".to_string()).unwrap());
+    assert!(store.hset("user:1".to_string(), "email".to_string(), "ada@example.com".to_string()).unwrap());
+    assert!(!store.hset("user:1".to_string(), "name".to_string(), "Ada".to_string()).unwrap());
+
+    assert_eq!(store.hget("user:1", "name").unwrap().as_deref(), Some("Ada"));
+    assert_eq!(store.hget("user:1", "phone").unwrap(), None);
+    assert_eq!(store.hget("missing", "name").unwrap(), None);
+    let all: Vec<(String, String)> = store.hgetall("user:1").unwrap().into_iter().collect();
+    assert_eq!(all, [("email".to_string(), "ada@example.com".to_string()), ("name".to_string(), "Ada".to_string())]);
+    assert!(store.hgetall("missing").unwrap().is_empty());
+}
+
+#[test]
+fn hash_and_list_ops_reject_other_values() {
+    let dir = TempDir::new().unwrap();
+    let mut store = KvStore::open(dir.path()).unwrap();
+    store.set("plain".to_string(), "just a string".to_string()).unwrap();
+    store.rpush("list".to_string(), "a".to_string()).unwrap();
+
+    assert!(matches!(store.hget("plain", "f"), Err(KvError::Serde(_))));
+    assert!(matches!(store.hgetall("list"), Err(KvError::Serde(_))));
+    assert!(matches!(store.hset("plain".to_string(), "f".to_string(), "v".to_string()), Err(KvError::Serde(_))));
+    assert!(matches!(store.rpush("plain".to_string(), "a".to_string()), Err(KvError::Serde(_))));
+    assert_eq!(store.get("plain").unwrap().as_deref(), Some("just a string"));
+}